@@ -0,0 +1,230 @@
+use crate::db_queries;
+use chrono::{DateTime, Local, TimeZone, Utc};
+use clap::Clap;
+use diesel::{Connection, PgConnection};
+use dotenv::dotenv;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use rask_lib::dates::{self, DateExpressionError};
+use rask_lib::models::{ApiToken, NewApiToken, User};
+use rask_lib::token::hash_token;
+use std::env;
+use thiserror::Error;
+
+/// How many random letters and digits a new token has.
+const TOKEN_LENGTH: usize = 40;
+
+#[derive(Clap)]
+pub struct Opts {
+    /// The API server is run if no subcommand is given.
+    #[clap(subcommand)]
+    pub subcommand: Option<SubCommand>,
+}
+
+#[derive(Clap)]
+pub enum SubCommand {
+    /// Manage the users who own tasks.
+    User(UserOpts),
+    /// Manage users' API tokens.
+    Token(TokenOpts),
+}
+
+#[derive(Clap)]
+pub struct UserOpts {
+    #[clap(subcommand)]
+    pub subcommand: UserSubCommand,
+}
+
+#[derive(Clap)]
+pub enum UserSubCommand {
+    /// Add a user, who can then be given tokens.
+    Create(UserCreateOpts),
+}
+
+#[derive(Clap)]
+pub struct UserCreateOpts {
+    pub name: String,
+}
+
+#[derive(Clap)]
+pub struct TokenOpts {
+    #[clap(subcommand)]
+    pub subcommand: TokenSubCommand,
+}
+
+#[derive(Clap)]
+pub enum TokenSubCommand {
+    /// Mint a random token for a user and print it. Only the token's hash is stored, so it can't
+    /// be shown again later.
+    Create(TokenCreateOpts),
+    /// List every token's ID, user, label, and expiry time.
+    List,
+    /// Revoke a token so that it can't be used anymore.
+    Revoke(TokenRevokeOpts),
+}
+
+#[derive(Clap)]
+pub struct TokenCreateOpts {
+    /// The name of the user who the token belongs to.
+    #[clap(long)]
+    pub user: String,
+
+    /// Which device the token is for, e.g. laptop.
+    #[clap(long)]
+    pub label: String,
+
+    /// A date like 2022-01-01, +90d, or eoy. The token stops working at the start of that day.
+    /// Tokens don't expire by default.
+    #[clap(long, parse(try_from_str = parse_expiry))]
+    pub expires: Option<DateTime<Utc>>,
+}
+
+#[derive(Clap)]
+pub struct TokenRevokeOpts {
+    /// The token's ID, as shown by `rask_api token list`.
+    pub token_id: i32,
+}
+
+#[derive(Debug, Error)]
+pub enum AdminError {
+    #[error("RASK_DATABASE_URL must be defined")]
+    MissingDatabaseUrl,
+
+    #[error(transparent)]
+    ConnectionError(#[from] diesel::ConnectionError),
+
+    #[error(transparent)]
+    DatabaseError(#[from] diesel::result::Error),
+
+    #[error(transparent)]
+    MigrationError(#[from] diesel_migrations::RunMigrationsError),
+
+    #[error("There's already a user named {0:?}")]
+    DuplicateUser(String),
+
+    #[error("There's no user named {0:?}")]
+    UnknownUser(String),
+
+    #[error("There's no token with ID {0}")]
+    UnknownToken(i32),
+}
+
+type Result<T, E = AdminError> = std::result::Result<T, E>;
+
+/// Parses a date expression like `2022-01-01` or `+90d` into the start of that day, local time.
+fn parse_expiry(expiry: &str) -> Result<DateTime<Utc>, DateExpressionError> {
+    let date = dates::parse_date_expression(expiry, Local::today().naive_local(), "%Y-%m-%d")?;
+
+    Local
+        .from_local_datetime(&date.and_hms(0, 0, 0))
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+        .ok_or_else(|| DateExpressionError::OutOfRange(expiry.to_string()))
+}
+
+/// Returns a new random token.
+fn generate_token() -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+pub fn create_user(conn: &PgConnection, name: &str) -> Result<User> {
+    if db_queries::get_user_by_name(conn, name)?.is_some() {
+        return Err(AdminError::DuplicateUser(name.to_string()));
+    }
+
+    Ok(db_queries::create_user(conn, name)?)
+}
+
+/// Mints a token for the user named `user_name`. Returns the token's row, along with the token
+/// itself, which isn't stored anywhere.
+pub fn create_token(
+    conn: &PgConnection,
+    user_name: &str,
+    label: &str,
+    time_expires: Option<DateTime<Utc>>,
+) -> Result<(ApiToken, String)> {
+    let user = db_queries::get_user_by_name(conn, user_name)?
+        .ok_or_else(|| AdminError::UnknownUser(user_name.to_string()))?;
+
+    let token = generate_token();
+    let api_token = db_queries::create_token(
+        conn,
+        NewApiToken {
+            user_id: user.id,
+            token_hash: hash_token(&token),
+            label: Some(label.to_string()),
+            time_expires,
+        },
+    )?;
+
+    Ok((api_token, token))
+}
+
+pub fn revoke_token(conn: &PgConnection, token_id: i32) -> Result<ApiToken> {
+    db_queries::revoke_token(conn, token_id)?.ok_or(AdminError::UnknownToken(token_id))
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.with_timezone(&Local)
+        .format("%Y-%m-%d %H:%M")
+        .to_string()
+}
+
+fn print_tokens(tokens: &[(ApiToken, User)]) {
+    println!(
+        "{:>4}  {:<12}  {:<16}  {:<16}  Expires",
+        "ID", "User", "Label", "Created"
+    );
+
+    for (token, user) in tokens {
+        println!(
+            "{:>4}  {:<12}  {:<16}  {:<16}  {}",
+            token.id,
+            user.name,
+            token.label.as_deref().unwrap_or("-"),
+            format_time(token.time_created),
+            token
+                .time_expires
+                .map(format_time)
+                .unwrap_or_else(|| "never".to_string())
+        );
+    }
+}
+
+/// Runs an admin subcommand against the database at RASK_DATABASE_URL.
+pub fn run(subcommand: SubCommand) -> Result<()> {
+    dotenv().ok();
+
+    let db_url = env::var("RASK_DATABASE_URL").map_err(|_| AdminError::MissingDatabaseUrl)?;
+    let conn = PgConnection::establish(&db_url)?;
+    // The server might not have been started yet, e.g. if this is a new installation.
+    crate::embedded_migrations::run(&conn)?;
+
+    match subcommand {
+        SubCommand::User(UserOpts {
+            subcommand: UserSubCommand::Create(opts),
+        }) => {
+            let user = create_user(&conn, &opts.name)?;
+            println!("Created user {} ({})", user.id, user.name);
+        }
+        SubCommand::Token(TokenOpts { subcommand }) => match subcommand {
+            TokenSubCommand::Create(opts) => {
+                let (api_token, token) =
+                    create_token(&conn, &opts.user, &opts.label, opts.expires)?;
+                println!("Created token {} for {}:", api_token.id, opts.user);
+                println!("{}", token);
+            }
+            TokenSubCommand::List => print_tokens(&db_queries::get_tokens(&conn)?),
+            TokenSubCommand::Revoke(opts) => {
+                let api_token = revoke_token(&conn, opts.token_id)?;
+                println!("Revoked token {}", api_token.id);
+            }
+        },
+    }
+
+    Ok(())
+}
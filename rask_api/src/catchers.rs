@@ -0,0 +1,78 @@
+//! Error responses for requests that fail before reaching an endpoint, or that an endpoint
+//! answers with `None`. Their bodies have the same shape as RaskApiError's.
+
+use rask_lib::models::ErrorBody;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{catch, Request};
+
+fn error_body(code: &str, message: String) -> Json<ErrorBody> {
+    Json(ErrorBody {
+        code: code.to_string(),
+        message,
+        errors: vec![],
+    })
+}
+
+/// What a request guard found wrong with a request that it failed with a 400. Guards store it with
+/// `Request::local_cache`, so that `bad_request` can say what the problem was.
+pub struct BadRequestReason(pub Option<&'static str>);
+
+/// Requests with malformed Authorization or If-Match headers end up here.
+#[catch(400)]
+pub fn bad_request(request: &Request) -> Json<ErrorBody> {
+    let message = match request.local_cache(|| BadRequestReason(None)).0 {
+        Some(reason) => format!("The request was malformed; {}", reason),
+        None => "The request was malformed".to_string(),
+    };
+
+    error_body("bad_request", message)
+}
+
+#[catch(401)]
+pub fn unauthorized(_: &Request) -> Json<ErrorBody> {
+    error_body(
+        "unauthorized",
+        "The request needs a valid API token".to_string(),
+    )
+}
+
+#[catch(404)]
+pub fn not_found(request: &Request) -> Json<ErrorBody> {
+    error_body(
+        "not_found",
+        format!("Nothing was found at {}", request.uri()),
+    )
+}
+
+/// Requests that change a task or recurrence without saying which version they expect end up here.
+#[catch(428)]
+pub fn precondition_required(_: &Request) -> Json<ErrorBody> {
+    error_body(
+        "precondition_required",
+        "The request needs an If-Match header with the version from the ETag of a GET request"
+            .to_string(),
+    )
+}
+
+/// Forms other than tasks' and recurrences' are checked by Rocket, which doesn't say which of
+/// their fields were bad.
+#[catch(422)]
+pub fn unprocessable_entity(_: &Request) -> Json<ErrorBody> {
+    error_body(
+        "unprocessable_entity",
+        "The request body is missing fields or has invalid values".to_string(),
+    )
+}
+
+#[catch(default)]
+pub fn default(status: Status, _: &Request) -> Json<ErrorBody> {
+    error_body(
+        "error",
+        format!(
+            "{} {}",
+            status.code,
+            status.reason().unwrap_or("Unknown Error")
+        ),
+    )
+}
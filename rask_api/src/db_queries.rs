@@ -1,120 +1,1415 @@
-use diesel::dsl::{any, exists};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use diesel::dsl::{any, exists, not, now};
 use diesel::pg::Pg;
 use diesel::prelude::*;
 use diesel::select;
-use diesel::sql_types::Bool;
+use diesel::sql_types::{Bool, Integer};
 use diesel::PgConnection;
+use rask_lib::display_id::{lock_display_ids, next_display_id};
+use rask_lib::filter::{DateFilter, Filter, ModeFilter};
+use rask_lib::history::{record_recurrence_change, record_task_change, restore, TaskSnapshot};
 use rask_lib::models::{
-    Mode, NewRecurrenceTemplate, NewTask, RecurrenceTemplate, Task, MODE_ACTIVE, MODE_COMPLETED,
-    MODE_DELETED, MODE_PENDING,
+    ApiToken, Mode, NewApiToken, NewRecurrenceTemplate, NewTask, NewTaskAnnotation, ProjectTime,
+    RecurrenceChanges, RecurrenceTemplate, Tag, Task, TaskAnnotation, TaskDependency, TaskHistory,
+    TaskPatch, TaskRelations, TaskTag, TaskTimeLog, User,
 };
 use rask_lib::schema::api_token;
 use rask_lib::schema::recurrence_template;
+use rask_lib::schema::tag;
 use rask_lib::schema::task;
+use rask_lib::schema::task_annotation;
+use rask_lib::schema::task_dependency;
+use rask_lib::schema::task_history;
+use rask_lib::schema::task_tag;
+use rask_lib::schema::task_time_log;
+use rask_lib::schema::taskwarrior_uuid;
+use rask_lib::schema::user;
+use rask_lib::taskwarrior::TaskwarriorImport;
+use std::collections::{HashMap, HashSet};
 
 type SqlExpr<'a, Table, SqlType> = Box<dyn BoxableExpression<Table, Pg, SqlType = SqlType> + 'a>;
 
+// History
+
+pub fn get_latest_change(conn: &PgConnection, user_id: i32) -> QueryResult<Option<TaskHistory>> {
+    task_history::table
+        .filter(task_history::user_id.eq(user_id))
+        .order(task_history::id.desc())
+        .first(conn)
+        .optional()
+}
+
+/// Like `get_latest_change()`, but the change's row stays locked until the end of the current
+/// transaction, so that it's still the latest change when it's undone.
+pub fn lock_latest_change(conn: &PgConnection, user_id: i32) -> QueryResult<Option<TaskHistory>> {
+    task_history::table
+        .filter(task_history::user_id.eq(user_id))
+        .order(task_history::id.desc())
+        .for_update()
+        .first(conn)
+        .optional()
+}
+
+/// Reverts `change`, which should be its user's latest change, and removes it from the history.
+/// Only the task's or recurrence's own fields are reverted; its tags, annotations, and
+/// dependencies aren't part of the history.
+pub fn undo_change(conn: &PgConnection, change: &TaskHistory) -> QueryResult<()> {
+    conn.transaction(|| {
+        match (
+            change.task_id,
+            change.recurrence_template_id,
+            &change.before,
+        ) {
+            (Some(task_id), _, None) => {
+                diesel::delete(task::table.find(task_id)).execute(conn)?;
+            }
+            (Some(task_id), _, Some(before)) => {
+                let TaskSnapshot {
+                    task: mut before,
+                    tags,
+                } = restore(before)?;
+                // Snapshots from before there were users don't say who owns the task.
+                before.user_id = change.user_id;
+                before.display_id = restored_display_id(conn, &before)?;
+                // Undoing a change modifies the task, so its modification time moves forward.
+                before.time_modified = Utc::now();
+                // Undoing a change is a change too, so requests based on the undone version fail.
+                before.version = task::table
+                    .find(task_id)
+                    .select(task::version)
+                    .first::<i32>(conn)?
+                    + 1;
+                diesel::update(task::table.find(task_id))
+                    .set(&before)
+                    .execute(conn)?;
+                sync_time_log(conn, &before)?;
+                if let Some(tags) = tags {
+                    set_task_tags(conn, task_id, &tags)?;
+                }
+            }
+            (None, Some(recurrence_id), None) => {
+                // Tasks that were created from the recurrence outlive it.
+                diesel::update(task::table.filter(task::recurrence_template_id.eq(recurrence_id)))
+                    .set(task::recurrence_template_id.eq(None::<i32>))
+                    .execute(conn)?;
+                diesel::delete(recurrence_template::table.find(recurrence_id)).execute(conn)?;
+            }
+            (None, Some(recurrence_id), Some(before)) => {
+                let mut before: RecurrenceTemplate = restore(before)?;
+                before.user_id = change.user_id;
+                before.version = recurrence_template::table
+                    .find(recurrence_id)
+                    .select(recurrence_template::version)
+                    .first::<i32>(conn)?
+                    + 1;
+                diesel::update(recurrence_template::table.find(recurrence_id))
+                    .set(&before)
+                    .execute(conn)?;
+            }
+            (None, None, _) => {}
+        }
+
+        // Deleting a newly-created row will already have deleted this change via ON DELETE CASCADE.
+        diesel::delete(task_history::table.find(change.id)).execute(conn)?;
+
+        Ok(())
+    })
+}
+
 // Tasks
 
-pub fn alive_tasks<'a>() -> SqlExpr<'a, task::table, Bool> {
-    Box::new(task::mode.eq(any(vec![MODE_PENDING.0, MODE_ACTIVE.0])))
+/// Returns the display ID that `task` should have when it's restored from the history table.
+/// Its old display ID is kept if it's alive and none of its user's other tasks has taken that ID
+/// in the meantime.
+fn restored_display_id(conn: &PgConnection, task: &Task) -> QueryResult<Option<i32>> {
+    if !task.mode.is_alive() {
+        return Ok(None);
+    }
+
+    lock_display_ids(conn, task.user_id)?;
+    if let Some(display_id) = task.display_id {
+        let taken = select(exists(
+            task::table
+                .filter(task::user_id.eq(task.user_id))
+                .filter(task::display_id.eq(display_id))
+                .filter(task::id.ne(task.id)),
+        ))
+        .get_result::<bool>(conn)?;
+
+        if !taken {
+            return Ok(Some(display_id));
+        }
+    }
+
+    next_display_id(conn, task.user_id).map(Some)
+}
+
+/// Pending or active tasks that aren't waiting as of `today`.
+pub fn alive_tasks<'a>(today: NaiveDate) -> SqlExpr<'a, task::table, Bool> {
+    Box::new(
+        task::mode
+            .eq(any(Mode::ALIVE.to_vec()))
+            .and(task::wait_until.is_null().or(task::wait_until.le(today))),
+    )
+}
+
+/// Pending or active tasks whose wait date is after `today`.
+pub fn waiting_tasks<'a>(today: NaiveDate) -> SqlExpr<'a, task::table, Bool> {
+    Box::new(
+        task::mode
+            .eq(any(Mode::ALIVE.to_vec()))
+            .and(task::wait_until.gt(today)),
+    )
+}
+
+/// Tasks that have the tag named `tag_name`.
+pub fn has_tag<'a>(tag_name: &'a str) -> SqlExpr<'a, task::table, Bool> {
+    Box::new(
+        task::id.eq_any(
+            task_tag::table
+                .inner_join(tag::table)
+                .filter(tag::name.eq(tag_name))
+                .select(task_tag::task_id),
+        ),
+    )
+}
+
+pub fn get_tasks(
+    conn: &PgConnection,
+    user_id: i32,
+    include_deleted: bool,
+) -> QueryResult<Vec<Task>> {
+    let mut query = task::table
+        .filter(task::user_id.eq(user_id))
+        .order(task::id)
+        .into_boxed();
+
+    if !include_deleted {
+        query = query.filter(task::mode.ne(Mode::Deleted));
+    }
+
+    query.load(conn)
+}
+
+/// Alive tasks that have all of `tags` and none of `without_tags`.
+pub fn get_alive_tasks(
+    conn: &PgConnection,
+    user_id: i32,
+    tags: &[String],
+    without_tags: &[String],
+    today: NaiveDate,
+) -> QueryResult<Vec<Task>> {
+    let mut query = task::table
+        .filter(task::user_id.eq(user_id))
+        .filter(alive_tasks(today))
+        .order(task::id)
+        .into_boxed();
+
+    for tag_name in tags {
+        query = query.filter(has_tag(tag_name));
+    }
+
+    for tag_name in without_tags {
+        query = query.filter(not(has_tag(tag_name)));
+    }
+
+    query.load(conn)
+}
+
+pub fn get_waiting_tasks(
+    conn: &PgConnection,
+    user_id: i32,
+    today: NaiveDate,
+) -> QueryResult<Vec<Task>> {
+    task::table
+        .filter(task::user_id.eq(user_id))
+        .filter(waiting_tasks(today))
+        .order(task::id)
+        .load(conn)
+}
+
+/// Escapes `%`, `_`, and `\\` so that `text` matches itself in a LIKE pattern.
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
 }
 
-pub fn get_tasks(conn: &PgConnection) -> QueryResult<Vec<Task>> {
-    task::table.order(task::id).load(conn)
+/// Tasks that match `filter`, where `today` decides which tasks are still waiting.
+pub fn filter_expression<'a>(
+    filter: &'a Filter,
+    today: NaiveDate,
+) -> SqlExpr<'a, task::table, Bool> {
+    match filter {
+        Filter::Mode(ModeFilter::Pending) => Box::new(task::mode.eq(Mode::Pending)),
+        Filter::Mode(ModeFilter::Active) => Box::new(task::mode.eq(Mode::Active)),
+        Filter::Mode(ModeFilter::Completed) => Box::new(task::mode.eq(Mode::Completed)),
+        Filter::Mode(ModeFilter::Deleted) => Box::new(task::mode.eq(Mode::Deleted)),
+        Filter::Mode(ModeFilter::Alive) => alive_tasks(today),
+        Filter::Mode(ModeFilter::Waiting) => waiting_tasks(today),
+        Filter::Project(Some(project)) => Box::new(task::project.eq(project)),
+        Filter::Project(None) => Box::new(task::project.is_null()),
+        Filter::Priority(Some(priority)) => Box::new(task::priority.eq(priority)),
+        Filter::Priority(None) => Box::new(task::priority.is_null()),
+        Filter::Due(DateFilter::On(due)) => Box::new(task::due.eq(due)),
+        Filter::Due(DateFilter::Before(due)) => Box::new(task::due.lt(due)),
+        Filter::Due(DateFilter::After(due)) => Box::new(task::due.gt(due)),
+        Filter::Due(DateFilter::Unset) => Box::new(task::due.is_null()),
+        Filter::NameContains(text) => {
+            Box::new(task::name.ilike(format!("%{}%", escape_like(text))))
+        }
+        Filter::HasTag(tag_name) => has_tag(tag_name),
+        Filter::LacksTag(tag_name) => Box::new(not(has_tag(tag_name))),
+    }
 }
 
-pub fn get_alive_tasks(conn: &PgConnection) -> QueryResult<Vec<Task>> {
-    task::table.filter(alive_tasks()).order(task::id).load(conn)
+/// Tasks that match all of `filters`. Deleted tasks are left out unless `include_deleted`
+/// is set or one of the filters is a `mode:` filter.
+pub fn get_filtered_tasks(
+    conn: &PgConnection,
+    user_id: i32,
+    filters: &[Filter],
+    include_deleted: bool,
+    today: NaiveDate,
+) -> QueryResult<Vec<Task>> {
+    let mut query = task::table
+        .filter(task::user_id.eq(user_id))
+        .order(task::id)
+        .into_boxed();
+
+    if !include_deleted && !filters.iter().any(|f| matches!(f, Filter::Mode(_))) {
+        query = query.filter(task::mode.ne(Mode::Deleted));
+    }
+
+    for filter in filters {
+        query = query.filter(filter_expression(filter, today));
+    }
+
+    query.load(conn)
+}
+
+/// Tasks that were completed at or after `since` and before `until`, if they're given, in the
+/// order that they were completed.
+pub fn get_completed_tasks(
+    conn: &PgConnection,
+    user_id: i32,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+) -> QueryResult<Vec<Task>> {
+    let mut query = task::table
+        .filter(task::user_id.eq(user_id))
+        .filter(task::mode.eq(Mode::Completed))
+        .into_boxed();
+
+    if let Some(since) = since {
+        query = query.filter(task::time_completed.ge(since));
+    }
+    if let Some(until) = until {
+        query = query.filter(task::time_completed.lt(until));
+    }
+
+    query.order((task::time_completed, task::id)).load(conn)
 }
 
 pub fn get_task_by_id(
     conn: &PgConnection,
+    user_id: i32,
     task_id: i32,
     include_deleted: bool,
 ) -> QueryResult<Option<Task>> {
-    let mut query = task::table.find(task_id).into_boxed();
+    let mut query = task::table
+        .find(task_id)
+        .filter(task::user_id.eq(user_id))
+        .into_boxed();
 
     if !include_deleted {
-        query = query.filter(task::mode.ne(MODE_DELETED.0));
+        query = query.filter(task::mode.ne(Mode::Deleted));
     }
 
     query.first(conn).optional()
 }
 
-pub fn update_mode(conn: &PgConnection, task_id: i32, mode: Mode) -> QueryResult<Option<Task>> {
-    diesel::update(task::table.find(task_id))
-        .set(task::mode.eq(mode.0))
-        .get_result(conn)
+/// Returns the version of a user's task, or None if there's no such task. The task's row stays
+/// locked until the end of the current transaction, so that its version can be compared with the
+/// one that a request expects before the request changes it.
+pub fn lock_task_version(
+    conn: &PgConnection,
+    user_id: i32,
+    task_id: i32,
+) -> QueryResult<Option<i32>> {
+    task::table
+        .find(task_id)
+        .filter(task::user_id.eq(user_id))
+        .select(task::version)
+        .for_update()
+        .first(conn)
         .optional()
 }
 
-pub fn uncomplete_task(conn: &PgConnection, task_id: i32) -> QueryResult<Option<Task>> {
-    let result = get_task_by_id(conn, task_id, false)?;
+/// Like `get_task_by_id()`, including deleted tasks, but the task's row stays locked until the
+/// end of the current transaction, so that its mode can be checked before changing it.
+fn lock_task(conn: &PgConnection, user_id: i32, task_id: i32) -> QueryResult<Option<Task>> {
+    task::table
+        .find(task_id)
+        .filter(task::user_id.eq(user_id))
+        .for_update()
+        .first(conn)
+        .optional()
+}
+
+pub fn get_task_by_display_id(
+    conn: &PgConnection,
+    user_id: i32,
+    display_id: i32,
+) -> QueryResult<Option<Task>> {
+    task::table
+        .filter(task::user_id.eq(user_id))
+        .filter(task::display_id.eq(display_id))
+        .first(conn)
+        .optional()
+}
+
+/// Sets a task's mode. Tasks get a display ID when they become alive, and give it up when they die.
+/// Their time log is started when they become active, and stopped when they stop being active.
+pub fn update_mode(
+    conn: &PgConnection,
+    user_id: i32,
+    task_id: i32,
+    mode: Mode,
+    token_hash: &str,
+) -> QueryResult<Option<Task>> {
+    conn.transaction(|| {
+        let before = get_task_by_id(conn, user_id, task_id, true)?;
+
+        let display_id = if mode.is_alive() {
+            match before.as_ref().and_then(|task| task.display_id) {
+                Some(display_id) => Some(display_id),
+                None => Some(next_display_id(conn, user_id)?),
+            }
+        } else {
+            None
+        };
+
+        // Completing a task that's already completed doesn't change when it was completed.
+        let current_time = Utc::now();
+        let time_completed = match &before {
+            _ if mode != Mode::Completed => None,
+            Some(task) if task.mode == Mode::Completed => task.time_completed,
+            _ => Some(current_time),
+        };
+
+        let after = diesel::update(task::table.find(task_id).filter(task::user_id.eq(user_id)))
+            .set((
+                task::mode.eq(mode),
+                task::display_id.eq(display_id),
+                task::time_completed.eq(time_completed),
+                task::time_modified.eq(current_time),
+                task::version.eq(task::version + 1),
+            ))
+            .get_result(conn)
+            .optional()?;
+
+        if let Some(after) = &after {
+            sync_time_log(conn, after)?;
+            record_field_change(conn, token_hash, before.as_ref(), after)?;
+        }
+
+        Ok(after)
+    })
+}
+
+pub fn uncomplete_task(
+    conn: &PgConnection,
+    user_id: i32,
+    task_id: i32,
+    token_hash: &str,
+) -> QueryResult<Option<Task>> {
+    let result = get_task_by_id(conn, user_id, task_id, false)?;
 
     match result {
-        Some(task) if task.mode == MODE_COMPLETED.0 => update_mode(conn, task_id, MODE_PENDING),
+        Some(task) if task.mode == Mode::Completed => {
+            update_mode(conn, user_id, task_id, Mode::Pending, token_hash)
+        }
         x => Ok(x),
     }
 }
 
-pub fn create_task(conn: &PgConnection, new_task: NewTask) -> QueryResult<Task> {
-    diesel::insert_into(task::table)
-        .values(new_task)
-        .get_result(conn)
+/// Marks a pending task active. Tasks that aren't pending are returned unchanged.
+pub fn start_task(
+    conn: &PgConnection,
+    user_id: i32,
+    task_id: i32,
+    token_hash: &str,
+) -> QueryResult<Option<Task>> {
+    // The task stays locked between checking its mode and changing it, so that two requests
+    // can't both start it.
+    conn.transaction(|| match lock_task(conn, user_id, task_id)? {
+        Some(task) if task.mode == Mode::Pending => {
+            update_mode(conn, user_id, task_id, Mode::Active, token_hash)
+        }
+        x => Ok(x),
+    })
+}
+
+/// Marks an active task pending again. Tasks that aren't active are returned unchanged.
+pub fn stop_task(
+    conn: &PgConnection,
+    user_id: i32,
+    task_id: i32,
+    token_hash: &str,
+) -> QueryResult<Option<Task>> {
+    conn.transaction(|| match lock_task(conn, user_id, task_id)? {
+        Some(task) if task.mode == Mode::Active => {
+            update_mode(conn, user_id, task_id, Mode::Pending, token_hash)
+        }
+        x => Ok(x),
+    })
+}
+
+pub fn delete_task(
+    conn: &PgConnection,
+    user_id: i32,
+    task_id: i32,
+    token_hash: &str,
+) -> QueryResult<Option<Task>> {
+    let result = get_task_by_id(conn, user_id, task_id, false)?;
+
+    match result {
+        Some(_) => update_mode(conn, user_id, task_id, Mode::Deleted, token_hash),
+        None => Ok(None),
+    }
+}
+
+pub fn undelete_task(
+    conn: &PgConnection,
+    user_id: i32,
+    task_id: i32,
+    token_hash: &str,
+) -> QueryResult<Option<Task>> {
+    let result = get_task_by_id(conn, user_id, task_id, true)?;
+
+    match result {
+        Some(task) if task.mode == Mode::Deleted => {
+            update_mode(conn, user_id, task_id, Mode::Pending, token_hash)
+        }
+        x => Ok(x),
+    }
+}
+
+/// Records a change to a task's fields or mode, which leaves its tags as they were.
+fn record_field_change(
+    conn: &PgConnection,
+    token_hash: &str,
+    before: Option<&Task>,
+    after: &Task,
+) -> QueryResult<()> {
+    let after = TaskSnapshot::take(conn, after)?;
+    let before = before.map(|before| TaskSnapshot {
+        task: before.clone(),
+        tags: after.tags.clone(),
+    });
+    record_task_change(conn, token_hash, before.as_ref(), &after)
+}
+
+/// Creates a task with the tags named `tags`.
+pub fn create_task(
+    conn: &PgConnection,
+    user_id: i32,
+    new_task: NewTask,
+    tags: &[String],
+    token_hash: &str,
+) -> QueryResult<Task> {
+    conn.transaction(|| {
+        let task: Task = diesel::insert_into(task::table)
+            .values((
+                new_task,
+                task::display_id.eq(next_display_id(conn, user_id)?),
+                task::user_id.eq(user_id),
+            ))
+            .get_result(conn)?;
+
+        change_task_tags(conn, task.id, tags, &[])?;
+        record_task_change(conn, token_hash, None, &TaskSnapshot::take(conn, &task)?)?;
+
+        Ok(task)
+    })
 }
 
 pub fn update_task(
     conn: &PgConnection,
+    user_id: i32,
     task_id: i32,
     updated_fields: NewTask,
+    token_hash: &str,
+) -> QueryResult<Option<Task>> {
+    conn.transaction(|| {
+        let before = get_task_by_id(conn, user_id, task_id, true)?;
+
+        let after = diesel::update(task::table.find(task_id).filter(task::user_id.eq(user_id)))
+            .set((
+                updated_fields,
+                task::time_modified.eq(Utc::now()),
+                task::version.eq(task::version + 1),
+            ))
+            .get_result(conn)
+            .optional()?;
+
+        if let Some(after) = &after {
+            record_field_change(conn, token_hash, before.as_ref(), after)?;
+        }
+
+        Ok(after)
+    })
+}
+
+/// Applies `patch` to a task, leaving the fields that it doesn't mention alone, and adding and
+/// removing the tags that it names.
+pub fn patch_task(
+    conn: &PgConnection,
+    user_id: i32,
+    task_id: i32,
+    patch: TaskPatch,
+    token_hash: &str,
 ) -> QueryResult<Option<Task>> {
+    conn.transaction(|| {
+        let before = match get_task_by_id(conn, user_id, task_id, true)? {
+            Some(task) => TaskSnapshot::take(conn, &task)?,
+            None => return Ok(None),
+        };
+
+        let tags_changed = change_task_tags(conn, task_id, &patch.add_tags, &patch.remove_tags)?;
+        if patch.changes.is_empty() && !tags_changed {
+            return Ok(Some(before.task));
+        }
+
+        let current_time = Utc::now();
+        let update = diesel::update(task::table.find(task_id));
+        let after: Task = if patch.changes.is_empty() {
+            update
+                .set((
+                    task::time_modified.eq(current_time),
+                    task::version.eq(task::version + 1),
+                ))
+                .get_result(conn)?
+        } else {
+            update
+                .set((
+                    patch.changes,
+                    task::time_modified.eq(current_time),
+                    task::version.eq(task::version + 1),
+                ))
+                .get_result(conn)?
+        };
+
+        record_task_change(
+            conn,
+            token_hash,
+            Some(&before),
+            &TaskSnapshot::take(conn, &after)?,
+        )?;
+
+        Ok(Some(after))
+    })
+}
+
+/// Renumbers the display IDs of a user's pending and active tasks so that they run from 1 to N,
+/// keeping their existing order.
+pub fn compact_display_ids(conn: &PgConnection, user_id: i32) -> QueryResult<Vec<Task>> {
+    conn.transaction(|| {
+        lock_display_ids(conn, user_id)?;
+
+        // Postgres sorts nulls last, so tasks without a display ID are numbered after the others.
+        let tasks = task::table
+            .filter(task::user_id.eq(user_id))
+            .filter(task::mode.eq(any(Mode::ALIVE.to_vec())))
+            .order((task::display_id, task::id))
+            .for_update()
+            .load::<Task>(conn)?;
+
+        // Each task's new display ID is at most its old one, so the IDs never collide along the way.
+        tasks
+            .into_iter()
+            .zip(1..)
+            .map(|(task, display_id)| {
+                if task.display_id == Some(display_id) {
+                    Ok(task)
+                } else {
+                    diesel::update(task::table.find(task.id))
+                        .set(task::display_id.eq(display_id))
+                        .get_result(conn)
+                }
+            })
+            .collect()
+    })
+}
+
+// Tags
+
+/// Returns the names of each of `tasks`' tags, in the same order as `tasks`.
+pub fn get_tags(conn: &PgConnection, tasks: &[Task]) -> QueryResult<Vec<Vec<String>>> {
+    let task_tags = TaskTag::belonging_to(tasks)
+        .inner_join(tag::table)
+        .order(tag::name)
+        .load::<(TaskTag, Tag)>(conn)?;
+
+    Ok(task_tags
+        .grouped_by(tasks)
+        .into_iter()
+        .map(|task_tags| task_tags.into_iter().map(|(_, tag)| tag.name).collect())
+        .collect())
+}
+
+/// Sets a task's modification time to now, for changes to its tags, annotations, or
+/// dependencies, which aren't stored in the task's own row. Returns the updated task.
+fn touch_task(conn: &PgConnection, task_id: i32) -> QueryResult<Task> {
     diesel::update(task::table.find(task_id))
-        .set(updated_fields)
+        .set(task::time_modified.eq(Utc::now()))
+        .get_result(conn)
+}
+
+/// Adds the tag named `tag_name` to the task with ID `task_id`, creating the tag if it doesn't
+/// exist yet. Returns false if the task already had the tag.
+fn insert_task_tag(conn: &PgConnection, task_id: i32, tag_name: &str) -> QueryResult<bool> {
+    diesel::insert_into(tag::table)
+        .values(tag::name.eq(tag_name))
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+
+    let tag_id = tag::table
+        .filter(tag::name.eq(tag_name))
+        .select(tag::id)
+        .first(conn)?;
+
+    diesel::insert_into(task_tag::table)
+        .values(TaskTag { task_id, tag_id })
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .map(|inserted| inserted > 0)
+}
+
+/// Removes the tag named `tag_name` from the task with ID `task_id`. Returns false if the task
+/// didn't have the tag.
+fn delete_task_tag(conn: &PgConnection, task_id: i32, tag_name: &str) -> QueryResult<bool> {
+    diesel::delete(
+        task_tag::table
+            .filter(task_tag::task_id.eq(task_id))
+            .filter(
+                task_tag::tag_id.eq_any(tag::table.filter(tag::name.eq(tag_name)).select(tag::id)),
+            ),
+    )
+    .execute(conn)
+    .map(|removed| removed > 0)
+}
+
+/// Adds the tags named `add_tags` to the task with ID `task_id`, and removes the ones named
+/// `remove_tags`. Returns whether the task's tags changed.
+fn change_task_tags(
+    conn: &PgConnection,
+    task_id: i32,
+    add_tags: &[String],
+    remove_tags: &[String],
+) -> QueryResult<bool> {
+    let mut changed = false;
+    for tag_name in add_tags {
+        changed |= insert_task_tag(conn, task_id, tag_name)?;
+    }
+    for tag_name in remove_tags {
+        changed |= delete_task_tag(conn, task_id, tag_name)?;
+    }
+
+    Ok(changed)
+}
+
+/// Replaces the tags of the task with ID `task_id` with the ones named `tag_names`.
+fn set_task_tags(conn: &PgConnection, task_id: i32, tag_names: &[String]) -> QueryResult<()> {
+    diesel::delete(task_tag::table.filter(task_tag::task_id.eq(task_id))).execute(conn)?;
+    change_task_tags(conn, task_id, tag_names, &[]).map(|_| ())
+}
+
+/// Adds and removes a task's tags like `change_task_tags()`, and records the change if there was
+/// one. Returns None if there's no task with ID `task_id`.
+fn retag_task(
+    conn: &PgConnection,
+    user_id: i32,
+    task_id: i32,
+    add_tags: &[String],
+    remove_tags: &[String],
+    token_hash: &str,
+) -> QueryResult<Option<Task>> {
+    conn.transaction(|| {
+        let before = match get_task_by_id(conn, user_id, task_id, true)? {
+            Some(task) => TaskSnapshot::take(conn, &task)?,
+            None => return Ok(None),
+        };
+
+        if !change_task_tags(conn, task_id, add_tags, remove_tags)? {
+            return Ok(Some(before.task));
+        }
+
+        let after = touch_task(conn, task_id)?;
+        record_task_change(
+            conn,
+            token_hash,
+            Some(&before),
+            &TaskSnapshot::take(conn, &after)?,
+        )?;
+
+        Ok(Some(after))
+    })
+}
+
+/// Adds the tag named `tag_name` to a task, creating the tag if it doesn't exist yet.
+/// Returns None if there's no task with ID `task_id`.
+pub fn tag_task(
+    conn: &PgConnection,
+    user_id: i32,
+    task_id: i32,
+    tag_name: String,
+    token_hash: &str,
+) -> QueryResult<Option<Task>> {
+    retag_task(conn, user_id, task_id, &[tag_name], &[], token_hash)
+}
+
+/// Removes the tag named `tag_name` from a task, if the task has it.
+/// Returns None if there's no task with ID `task_id`.
+pub fn untag_task(
+    conn: &PgConnection,
+    user_id: i32,
+    task_id: i32,
+    tag_name: String,
+    token_hash: &str,
+) -> QueryResult<Option<Task>> {
+    retag_task(conn, user_id, task_id, &[], &[tag_name], token_hash)
+}
+
+// Dependencies
+
+/// Returns true if every task in `task_ids` exists and belongs to the user.
+pub fn owns_tasks(conn: &PgConnection, user_id: i32, task_ids: &[i32]) -> QueryResult<bool> {
+    let unique_ids = task_ids.iter().collect::<HashSet<_>>();
+    let owned = task::table
+        .filter(task::id.eq_any(task_ids))
+        .filter(task::user_id.eq(user_id))
+        .count()
+        .get_result::<i64>(conn)?;
+
+    Ok(owned as usize == unique_ids.len())
+}
+
+/// Returns true if making `task_id` depend on `depends_on_id` would create a cycle,
+/// i.e. if `depends_on_id` already depends on `task_id`, directly or indirectly.
+/// Locks the `task_dependency` table until the end of the current transaction, so that
+/// the answer stays correct until the new dependency is added.
+pub fn would_create_cycle(
+    conn: &PgConnection,
+    task_id: i32,
+    depends_on_id: i32,
+) -> QueryResult<bool> {
+    diesel::sql_query("LOCK TABLE task_dependency IN SHARE ROW EXCLUSIVE MODE").execute(conn)?;
+
+    let mut seen = HashSet::new();
+    let mut frontier = vec![depends_on_id];
+
+    while !frontier.is_empty() {
+        if frontier.contains(&task_id) {
+            return Ok(true);
+        }
+
+        seen.extend(frontier.iter().copied());
+        frontier = task_dependency::table
+            .filter(task_dependency::task_id.eq_any(&frontier))
+            .select(task_dependency::depends_on_id)
+            .load::<i32>(conn)?
+            .into_iter()
+            .filter(|id| !seen.contains(id))
+            .collect();
+    }
+
+    Ok(false)
+}
+
+/// Makes `task_id` depend on `depends_on_id`, without checking who owns them or changing their
+/// modification times. Returns false if the dependency already existed.
+fn insert_dependency(conn: &PgConnection, task_id: i32, depends_on_id: i32) -> QueryResult<bool> {
+    diesel::insert_into(task_dependency::table)
+        .values(TaskDependency {
+            task_id,
+            depends_on_id,
+        })
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .map(|inserted| inserted > 0)
+}
+
+/// Makes `task_id` depend on `depends_on_id`. Callers should check `would_create_cycle()` first.
+/// Returns None if either task doesn't exist or belongs to another user, so dependencies never
+/// cross between users.
+pub fn add_dependency(
+    conn: &PgConnection,
+    user_id: i32,
+    task_id: i32,
+    depends_on_id: i32,
+) -> QueryResult<Option<Task>> {
+    conn.transaction(|| {
+        let task = get_task_by_id(conn, user_id, task_id, true)?;
+        let depends_on = get_task_by_id(conn, user_id, depends_on_id, true)?;
+
+        match (task, depends_on) {
+            (Some(task), Some(_)) => {
+                if insert_dependency(conn, task_id, depends_on_id)? {
+                    touch_task(conn, task_id).map(Some)
+                } else {
+                    Ok(Some(task))
+                }
+            }
+            _ => Ok(None),
+        }
+    })
+}
+
+/// Returns None if there's no task with ID `task_id`.
+pub fn remove_dependency(
+    conn: &PgConnection,
+    user_id: i32,
+    task_id: i32,
+    depends_on_id: i32,
+) -> QueryResult<Option<Task>> {
+    conn.transaction(|| {
+        let task = match get_task_by_id(conn, user_id, task_id, true)? {
+            Some(task) => task,
+            None => return Ok(None),
+        };
+
+        let removed =
+            diesel::delete(task_dependency::table.find((task_id, depends_on_id))).execute(conn)?;
+
+        if removed > 0 {
+            touch_task(conn, task_id).map(Some)
+        } else {
+            Ok(Some(task))
+        }
+    })
+}
+
+// Relations
+
+/// Pairs each of `tasks` with its tags and unfinished dependencies.
+pub fn with_relations(
+    conn: &PgConnection,
+    tasks: Vec<Task>,
+) -> QueryResult<Vec<(Task, TaskRelations)>> {
+    let task_ids: Vec<i32> = tasks.iter().map(|task| task.id).collect();
+    let index_by_id: HashMap<i32, usize> = task_ids
+        .iter()
+        .enumerate()
+        .map(|(i, &task_id)| (task_id, i))
+        .collect();
+
+    let mut relations: Vec<TaskRelations> = get_tags(conn, &tasks)?
+        .into_iter()
+        .map(|tags| TaskRelations {
+            tags,
+            ..TaskRelations::default()
+        })
+        .collect();
+
+    // Pending or active tasks, including ones that are waiting.
+    let unfinished_task_ids = || {
+        task::table
+            .filter(task::mode.eq(any(Mode::ALIVE.to_vec())))
+            .select(task::id)
+    };
+
+    let blocked_by = task_dependency::table
+        .filter(task_dependency::task_id.eq_any(&task_ids))
+        .filter(task_dependency::depends_on_id.eq_any(unfinished_task_ids()))
+        .order(task_dependency::depends_on_id)
+        .load::<TaskDependency>(conn)?;
+
+    for dependency in blocked_by {
+        relations[index_by_id[&dependency.task_id]]
+            .blocked_by
+            .push(dependency.depends_on_id);
+    }
+
+    let blocks = task_dependency::table
+        .filter(task_dependency::depends_on_id.eq_any(&task_ids))
+        .filter(task_dependency::task_id.eq_any(unfinished_task_ids()))
+        .order(task_dependency::task_id)
+        .load::<TaskDependency>(conn)?;
+
+    for dependency in blocks {
+        let i = index_by_id[&dependency.depends_on_id];
+        if tasks[i].mode.is_alive() {
+            relations[i].blocks.push(dependency.task_id);
+        }
+    }
+
+    Ok(tasks.into_iter().zip(relations).collect())
+}
+
+/// Like `with_relations()`, but for a single task that might not exist.
+pub fn maybe_with_relations(
+    conn: &PgConnection,
+    task: Option<Task>,
+) -> QueryResult<Option<(Task, TaskRelations)>> {
+    Ok(with_relations(conn, task.into_iter().collect())?.pop())
+}
+
+// Annotations
+
+pub fn get_annotations(conn: &PgConnection, task: &Task) -> QueryResult<Vec<TaskAnnotation>> {
+    TaskAnnotation::belonging_to(task)
+        .order(task_annotation::id)
+        .load(conn)
+}
+
+fn insert_annotation(
+    conn: &PgConnection,
+    task_id: i32,
+    body: String,
+) -> QueryResult<TaskAnnotation> {
+    diesel::insert_into(task_annotation::table)
+        .values(NewTaskAnnotation { task_id, body })
+        .get_result(conn)
+}
+
+/// Returns None if there's no task with ID `task_id`.
+pub fn annotate_task(
+    conn: &PgConnection,
+    user_id: i32,
+    task_id: i32,
+    body: String,
+) -> QueryResult<Option<TaskAnnotation>> {
+    conn.transaction(|| match get_task_by_id(conn, user_id, task_id, true)? {
+        Some(task) => {
+            let annotation = insert_annotation(conn, task.id, body)?;
+            touch_task(conn, task.id)?;
+            Ok(Some(annotation))
+        }
+        None => Ok(None),
+    })
+}
+
+/// Returns the removed annotation, or None if `task_id` has no annotation with ID `annotation_id`.
+pub fn remove_annotation(
+    conn: &PgConnection,
+    user_id: i32,
+    task_id: i32,
+    annotation_id: i32,
+) -> QueryResult<Option<TaskAnnotation>> {
+    conn.transaction(|| {
+        if get_task_by_id(conn, user_id, task_id, true)?.is_none() {
+            return Ok(None);
+        }
+
+        let removed = diesel::delete(
+            task_annotation::table
+                .filter(task_annotation::id.eq(annotation_id))
+                .filter(task_annotation::task_id.eq(task_id)),
+        )
+        .get_result(conn)
+        .optional()?;
+
+        if removed.is_some() {
+            touch_task(conn, task_id)?;
+        }
+        Ok(removed)
+    })
+}
+
+// Time log
+
+/// Starts an interval in `task`'s time log if it's active and doesn't have one in progress,
+/// or stops its interval in progress if it isn't active.
+fn sync_time_log(conn: &PgConnection, task: &Task) -> QueryResult<()> {
+    if task.mode == Mode::Active {
+        diesel::insert_into(task_time_log::table)
+            .values(task_time_log::task_id.eq(task.id))
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+    } else {
+        diesel::update(
+            task_time_log::table
+                .filter(task_time_log::task_id.eq(task.id))
+                .filter(task_time_log::time_stopped.is_null()),
+        )
+        .set(task_time_log::time_stopped.eq(now))
+        .execute(conn)?;
+    }
+
+    Ok(())
+}
+
+pub fn get_time_log(conn: &PgConnection, task: &Task) -> QueryResult<Vec<TaskTimeLog>> {
+    TaskTimeLog::belonging_to(task)
+        .order(task_time_log::id)
+        .load(conn)
+}
+
+/// The total time that's been spent on each of a user's projects' tasks, including deleted
+/// tasks and intervals that are still in progress, with the projects that took the most time
+/// first.
+pub fn get_project_times(conn: &PgConnection, user_id: i32) -> QueryResult<Vec<ProjectTime>> {
+    diesel::sql_query(
+        "SELECT task.project, SUM(FLOOR(EXTRACT(EPOCH FROM \
+             COALESCE(task_time_log.time_stopped, now()) - task_time_log.time_started \
+         )))::BIGINT AS seconds \
+         FROM task_time_log INNER JOIN task ON task.id = task_time_log.task_id \
+         WHERE task.user_id = $1 \
+         GROUP BY task.project \
+         ORDER BY seconds DESC, task.project NULLS FIRST",
+    )
+    .bind::<Integer, _>(user_id)
+    .load(conn)
+}
+
+// Taskwarrior
+
+/// Inserts the tasks and recurrence templates in `import` for a user, along with the tasks' tags,
+/// annotations, and dependencies on other tasks in the same import. Returns the new rows.
+///
+/// A Taskwarrior recurring task's latest recurrence is imported as an ordinary task, so its
+/// template's due date is moved past that recurrence to keep the daemon from creating it again.
+///
+/// Tasks and recurrence templates whose Taskwarrior UUIDs the user has already imported are
+/// skipped, but new tasks can still depend on them or be their recurrences.
+pub fn import_taskwarrior(
+    conn: &PgConnection,
+    user_id: i32,
+    import: TaskwarriorImport,
+    token_hash: &str,
+) -> QueryResult<(Vec<Task>, Vec<RecurrenceTemplate>)> {
+    conn.transaction(|| {
+        let uuids = import
+            .recurrences
+            .iter()
+            .map(|imported| &imported.uuid)
+            .chain(import.tasks.iter().flat_map(|imported| {
+                std::iter::once(&imported.uuid)
+                    .chain(&imported.parent)
+                    .chain(&imported.depends)
+            }))
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut existing_task_ids = HashMap::new();
+        let mut existing_recurrence_ids = HashMap::new();
+        for (uuid, task_id, recurrence_id) in taskwarrior_uuid::table
+            .filter(taskwarrior_uuid::user_id.eq(user_id))
+            .filter(taskwarrior_uuid::uuid.eq_any(uuids))
+            .select((
+                taskwarrior_uuid::uuid,
+                taskwarrior_uuid::task_id,
+                taskwarrior_uuid::recurrence_template_id,
+            ))
+            .load::<(String, Option<i32>, Option<i32>)>(conn)?
+        {
+            if let Some(task_id) = task_id {
+                existing_task_ids.insert(uuid.clone(), task_id);
+            }
+            if let Some(recurrence_id) = recurrence_id {
+                existing_recurrence_ids.insert(uuid, recurrence_id);
+            }
+        }
+
+        let mut recurrences = vec![];
+        let mut recurrence_ids = HashMap::new();
+        for imported in import.recurrences {
+            if existing_recurrence_ids.contains_key(&imported.uuid)
+                || recurrence_ids.contains_key(&imported.uuid)
+            {
+                continue;
+            }
+
+            let recurrence = diesel::insert_into(recurrence_template::table)
+                .values((
+                    imported.recurrence,
+                    recurrence_template::user_id.eq(user_id),
+                ))
+                .get_result::<RecurrenceTemplate>(conn)?;
+            recurrence_ids.insert(imported.uuid, recurrences.len());
+            recurrences.push(recurrence);
+        }
+
+        let mut tasks = vec![];
+        let mut task_ids = HashMap::new();
+        let mut dependencies = vec![];
+        for imported in import.tasks {
+            if existing_task_ids.contains_key(&imported.uuid)
+                || task_ids.contains_key(&imported.uuid)
+            {
+                continue;
+            }
+
+            let mut new_task = imported.task;
+            let recurrence_index = imported
+                .parent
+                .as_ref()
+                .and_then(|parent| recurrence_ids.get(parent).copied());
+            new_task.recurrence_template_id = match recurrence_index {
+                Some(i) => Some(recurrences[i].id),
+                None => imported
+                    .parent
+                    .as_ref()
+                    .and_then(|parent| existing_recurrence_ids.get(parent).copied()),
+            };
+
+            let display_id = if new_task.mode.is_alive() {
+                Some(next_display_id(conn, user_id)?)
+            } else {
+                None
+            };
+            let task = diesel::insert_into(task::table)
+                .values((
+                    new_task,
+                    task::display_id.eq(display_id),
+                    task::user_id.eq(user_id),
+                ))
+                .get_result::<Task>(conn)?;
+
+            // These don't change the task's imported modification time.
+            for tag_name in &imported.tags {
+                insert_task_tag(conn, task.id, tag_name)?;
+            }
+            for body in imported.annotations {
+                insert_annotation(conn, task.id, body)?;
+            }
+
+            if let (Some(i), Some(due)) = (recurrence_index, task.due) {
+                let recurrence = &mut recurrences[i];
+                let next_due = due + Duration::days(recurrence.days_between_recurrences.into());
+                if next_due > recurrence.due {
+                    recurrence.due = next_due;
+                }
+            }
+
+            dependencies.extend(imported.depends.into_iter().map(|uuid| (task.id, uuid)));
+            task_ids.insert(imported.uuid, task.id);
+            tasks.push(task);
+        }
+
+        // Dependencies on tasks that weren't part of this import or an earlier one are dropped.
+        for (task_id, depends_on_uuid) in dependencies {
+            let depends_on_id = task_ids
+                .get(&depends_on_uuid)
+                .or_else(|| existing_task_ids.get(&depends_on_uuid));
+            if let Some(&depends_on_id) = depends_on_id {
+                if !would_create_cycle(conn, task_id, depends_on_id)? {
+                    insert_dependency(conn, task_id, depends_on_id)?;
+                }
+            }
+        }
+
+        diesel::insert_into(taskwarrior_uuid::table)
+            .values(
+                recurrence_ids
+                    .into_iter()
+                    .map(|(uuid, i)| {
+                        (
+                            taskwarrior_uuid::user_id.eq(user_id),
+                            taskwarrior_uuid::uuid.eq(uuid),
+                            taskwarrior_uuid::recurrence_template_id.eq(recurrences[i].id),
+                        )
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .execute(conn)?;
+        diesel::insert_into(taskwarrior_uuid::table)
+            .values(
+                task_ids
+                    .into_iter()
+                    .map(|(uuid, task_id)| {
+                        (
+                            taskwarrior_uuid::user_id.eq(user_id),
+                            taskwarrior_uuid::uuid.eq(uuid),
+                            taskwarrior_uuid::task_id.eq(task_id),
+                        )
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .execute(conn)?;
+
+        for recurrence in &recurrences {
+            diesel::update(recurrence_template::table.find(recurrence.id))
+                .set(recurrence_template::due.eq(recurrence.due))
+                .execute(conn)?;
+            record_recurrence_change(conn, token_hash, None, recurrence)?;
+        }
+        for task in &tasks {
+            record_task_change(conn, token_hash, None, &TaskSnapshot::take(conn, task)?)?;
+        }
+
+        Ok((tasks, recurrences))
+    })
+}
+
+// Users and tokens
+
+pub fn get_user_by_name(conn: &PgConnection, name: &str) -> QueryResult<Option<User>> {
+    user::table
+        .filter(user::name.eq(name))
+        .first(conn)
+        .optional()
+}
+
+pub fn create_user(conn: &PgConnection, name: &str) -> QueryResult<User> {
+    diesel::insert_into(user::table)
+        .values(user::name.eq(name))
         .get_result(conn)
+}
+
+/// Returns the user who owns the token with hash `token_hash`, or None if there's no such token
+/// or it has expired.
+pub fn get_token_user(conn: &PgConnection, token_hash: &str) -> QueryResult<Option<User>> {
+    api_token::table
+        .inner_join(user::table)
+        .filter(api_token::token_hash.eq(token_hash))
+        .filter(
+            api_token::time_expires
+                .is_null()
+                .or(api_token::time_expires.gt(now)),
+        )
+        .select(user::all_columns)
+        .first(conn)
         .optional()
 }
 
-// Tokens
+pub fn create_token(conn: &PgConnection, new_token: NewApiToken) -> QueryResult<ApiToken> {
+    diesel::insert_into(api_token::table)
+        .values(new_token)
+        .get_result(conn)
+}
+
+/// Every token, including expired ones, paired with the user who owns it.
+pub fn get_tokens(conn: &PgConnection) -> QueryResult<Vec<(ApiToken, User)>> {
+    api_token::table
+        .inner_join(user::table)
+        .order((user::name, api_token::id))
+        .load(conn)
+}
 
-pub fn token_exists(conn: &PgConnection, token: &str) -> QueryResult<bool> {
-    select(exists(api_token::table.find(token))).get_result(conn)
+/// Deletes a token so that it can't be used anymore.
+/// Returns None if there's no token with ID `token_id`.
+pub fn revoke_token(conn: &PgConnection, token_id: i32) -> QueryResult<Option<ApiToken>> {
+    diesel::delete(api_token::table.find(token_id))
+        .get_result(conn)
+        .optional()
 }
 
 // Recurrence templates
 
 pub fn create_recurrence(
     conn: &PgConnection,
+    user_id: i32,
     new_recurrence: NewRecurrenceTemplate,
+    token_hash: &str,
 ) -> QueryResult<RecurrenceTemplate> {
-    diesel::insert_into(recurrence_template::table)
-        .values(new_recurrence)
-        .get_result(conn)
+    conn.transaction(|| {
+        let recurrence = diesel::insert_into(recurrence_template::table)
+            .values((new_recurrence, recurrence_template::user_id.eq(user_id)))
+            .get_result(conn)?;
+
+        record_recurrence_change(conn, token_hash, None, &recurrence)?;
+
+        Ok(recurrence)
+    })
 }
 
 pub fn get_recurrence_by_id(
     conn: &PgConnection,
+    user_id: i32,
     recurrence_id: i32,
 ) -> QueryResult<Option<RecurrenceTemplate>> {
     recurrence_template::table
         .find(recurrence_id)
+        .filter(recurrence_template::user_id.eq(user_id))
         .first(conn)
         .optional()
 }
 
-pub fn get_recurrences(conn: &PgConnection) -> QueryResult<Vec<RecurrenceTemplate>> {
+/// Like `lock_task_version()`, but for recurrence templates.
+pub fn lock_recurrence_version(
+    conn: &PgConnection,
+    user_id: i32,
+    recurrence_id: i32,
+) -> QueryResult<Option<i32>> {
     recurrence_template::table
+        .find(recurrence_id)
+        .filter(recurrence_template::user_id.eq(user_id))
+        .select(recurrence_template::version)
+        .for_update()
+        .first(conn)
+        .optional()
+}
+
+pub fn get_recurrences(conn: &PgConnection, user_id: i32) -> QueryResult<Vec<RecurrenceTemplate>> {
+    recurrence_template::table
+        .filter(recurrence_template::user_id.eq(user_id))
         .order(recurrence_template::id)
         .load(conn)
 }
 
 pub fn update_recurrence(
     conn: &PgConnection,
+    user_id: i32,
     recurrence_id: i32,
     updated_fields: NewRecurrenceTemplate,
+    token_hash: &str,
 ) -> QueryResult<Option<RecurrenceTemplate>> {
-    diesel::update(recurrence_template::table.find(recurrence_id))
-        .set(updated_fields)
+    conn.transaction(|| {
+        let before = get_recurrence_by_id(conn, user_id, recurrence_id)?;
+
+        let after = diesel::update(
+            recurrence_template::table
+                .find(recurrence_id)
+                .filter(recurrence_template::user_id.eq(user_id)),
+        )
+        .set((
+            updated_fields,
+            recurrence_template::version.eq(recurrence_template::version + 1),
+        ))
         .get_result(conn)
-        .optional()
+        .optional()?;
+
+        if let Some(after) = &after {
+            record_recurrence_change(conn, token_hash, before.as_ref(), after)?;
+        }
+
+        Ok(after)
+    })
+}
+
+/// Applies `changes` to a recurrence template, leaving the fields that they don't mention alone.
+pub fn patch_recurrence(
+    conn: &PgConnection,
+    user_id: i32,
+    recurrence_id: i32,
+    changes: RecurrenceChanges,
+    token_hash: &str,
+) -> QueryResult<Option<RecurrenceTemplate>> {
+    conn.transaction(|| {
+        let before = get_recurrence_by_id(conn, user_id, recurrence_id)?;
+        // Diesel refuses to run an UPDATE that doesn't set anything.
+        if changes.is_empty() {
+            return Ok(before);
+        }
+
+        let after = diesel::update(
+            recurrence_template::table
+                .find(recurrence_id)
+                .filter(recurrence_template::user_id.eq(user_id)),
+        )
+        .set((
+            changes,
+            recurrence_template::version.eq(recurrence_template::version + 1),
+        ))
+        .get_result(conn)
+        .optional()?;
+
+        if let Some(after) = &after {
+            record_recurrence_change(conn, token_hash, before.as_ref(), after)?;
+        }
+
+        Ok(after)
+    })
 }
@@ -1,15 +1,26 @@
 use crate::db::DBConn;
 use crate::db_queries;
-use crate::form::{RecurrenceForm, TaskForm, WrappedNewRecurrenceTemplate, WrappedNewTask};
+use crate::form::{
+    AnnotationForm, DependencyForm, FormOrJson, InvalidBody, JsonObject, RecurrenceForm, TagForm,
+    TaskForm, TaskwarriorTasks, WrappedNewRecurrenceTemplate, WrappedNewTask,
+};
 use crate::token::ApiToken;
-use rask_lib::models::{RecurrenceTemplate, Task, MODE_COMPLETED};
+use crate::version::{IfMatch, WithETag};
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+use diesel::Connection;
+use rask_lib::filter::{Filter, FilterParseError};
+use rask_lib::models::{
+    ErrorBody, Mode, ProjectTime, RecurrenceChanges, RecurrenceTemplate, Task, TaskAnnotation,
+    TaskHistory, TaskPatch, TaskRelations, TaskTimeLog, TaskView,
+};
+use rask_lib::taskwarrior::{self, ImportedRows};
+use rask_lib::urgency::UrgencyCoefficients;
 use rocket::form::Form;
-use rocket::http::{ContentType, Status};
-use rocket::response::status::Created;
-use rocket::response::{Responder, Response};
+use rocket::http::Status;
+use rocket::response::status::{self, Created};
+use rocket::response::Responder;
 use rocket::serde::json::Json;
-use rocket::{get, post};
-use std::io::Cursor;
+use rocket::{get, patch, post, State};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -19,130 +30,746 @@ pub enum RaskApiError {
 
     #[error("Intentional error thrown for use in tests")]
     IntentionalErrorForTesting,
+
+    #[error(
+        "Task {task_id} can't depend on task {depends_on_id}, because that would create a cycle"
+    )]
+    DependencyCycle { task_id: i32, depends_on_id: i32 },
+
+    #[error(transparent)]
+    InvalidFilter(#[from] FilterParseError),
+
+    #[error("Invalid timestamp {0:?}; expected e.g. 2021-09-01T00:00:00-07:00 or 2021-09-01")]
+    InvalidTimestamp(String),
+
+    #[error("Invalid date {0:?}; expected e.g. 2021-09-01")]
+    InvalidDate(String),
+
+    #[error("Invalid UTC offset {0:?}; expected a number of seconds east of UTC, under a day")]
+    InvalidUtcOffset(String),
+
+    #[error("{}", .0.message)]
+    InvalidBody(InvalidBody),
+
+    #[error(
+        "Expected version {expected}, but it's been changed since then and is now at version \
+         {current}; fetch it again and retry"
+    )]
+    VersionMismatch { expected: i32, current: i32 },
+
+    #[error("Change {history_id} can't be undone, because change {latest_id} is more recent")]
+    NotLatestChange { history_id: i32, latest_id: i32 },
+}
+
+impl From<InvalidBody> for RaskApiError {
+    fn from(invalid_body: InvalidBody) -> Self {
+        RaskApiError::InvalidBody(invalid_body)
+    }
+}
+
+impl RaskApiError {
+    fn status(&self) -> Status {
+        match self {
+            RaskApiError::DependencyCycle { .. }
+            | RaskApiError::InvalidFilter(_)
+            | RaskApiError::InvalidTimestamp(_)
+            | RaskApiError::InvalidDate(_)
+            | RaskApiError::InvalidUtcOffset(_)
+            | RaskApiError::InvalidBody(_) => Status::UnprocessableEntity,
+            RaskApiError::VersionMismatch { .. } => Status::PreconditionFailed,
+            RaskApiError::NotLatestChange { .. } => Status::Conflict,
+            _ => Status::InternalServerError,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            RaskApiError::DatabaseError(_) | RaskApiError::IntentionalErrorForTesting => {
+                "internal_error"
+            }
+            RaskApiError::DependencyCycle { .. } => "dependency_cycle",
+            RaskApiError::InvalidFilter(_) => "invalid_filter",
+            RaskApiError::InvalidTimestamp(_) => "invalid_timestamp",
+            RaskApiError::InvalidDate(_) => "invalid_date",
+            RaskApiError::InvalidUtcOffset(_) => "invalid_utc_offset",
+            RaskApiError::InvalidBody(_) => "invalid_body",
+            RaskApiError::VersionMismatch { .. } => "version_mismatch",
+            RaskApiError::NotLatestChange { .. } => "not_latest_change",
+        }
+    }
 }
 
 impl<'r> Responder<'r, 'static> for RaskApiError {
-    /// Respond with a 500 status code.
-    fn respond_to(self, _: &'r rocket::Request<'_>) -> rocket::response::Result<'static> {
-        let body = format!("Error: {}", self);
-        let response = Response::build()
-            .status(Status::InternalServerError)
-            .header(ContentType::Plain)
-            .sized_body(body.len(), Cursor::new(body))
-            .finalize();
-
-        Ok(response)
+    /// Respond with a 422 status code for invalid requests, a 412 for requests whose If-Match
+    /// header is out of date, a 409 for undoing a change that isn't the latest, or a 500
+    /// otherwise. The body is an
+    /// ErrorBody, which lists each bad field if the request body was invalid.
+    fn respond_to(self, request: &'r rocket::Request<'_>) -> rocket::response::Result<'static> {
+        let status = self.status();
+        let body = ErrorBody {
+            code: self.code().to_string(),
+            message: self.to_string(),
+            errors: match self {
+                RaskApiError::InvalidBody(invalid_body) => invalid_body.errors,
+                _ => vec![],
+            },
+        };
+
+        status::Custom(status, Json(body)).respond_to(request)
     }
 }
 
 type Result<T, E = RaskApiError> = std::result::Result<T, E>;
 
+/// Fails with a VersionMismatch unless the row that's about to be changed is at the version that
+/// the request's If-Match header expects. Rows that don't exist pass, so that they can 404.
+fn check_version(if_match: &IfMatch, current: Option<i32>) -> Result<()> {
+    match current {
+        Some(current) if current != if_match.0 => Err(RaskApiError::VersionMismatch {
+            expected: if_match.0,
+            current,
+        }),
+        _ => Ok(()),
+    }
+}
+
 // Tasks
 
+/// A task paired with its relations, as returned by `db_queries::with_relations()`.
+type RelatedTask = (Task, TaskRelations);
+
+/// Wraps each of `tasks` in a TaskView.
+fn task_views(tasks: Vec<RelatedTask>, coefficients: &UrgencyCoefficients) -> Vec<TaskView> {
+    tasks
+        .into_iter()
+        .map(|(task, relations)| TaskView::new(task, relations, coefficients))
+        .collect()
+}
+
+/// Wraps `task`, if there is one, in a TaskView.
+fn task_view(
+    task: Option<RelatedTask>,
+    coefficients: &UrgencyCoefficients,
+) -> Option<Json<TaskView>> {
+    task.map(|(task, relations)| Json(TaskView::new(task, relations, coefficients)))
+}
+
+/// A task paired with its relations, annotations, and time log.
+type DetailedTask = (RelatedTask, Vec<TaskAnnotation>, Vec<TaskTimeLog>);
+
+/// Looks up `task`'s relations, annotations, and time log, if there is a task.
+fn with_details(
+    conn: &diesel::PgConnection,
+    task: Option<Task>,
+) -> diesel::QueryResult<Option<DetailedTask>> {
+    let details = match &task {
+        Some(task) => Some((
+            db_queries::get_annotations(conn, task)?,
+            db_queries::get_time_log(conn, task)?,
+        )),
+        None => None,
+    };
+
+    Ok(db_queries::maybe_with_relations(conn, task)?
+        .zip(details)
+        .map(|(task, (annotations, time_log))| (task, annotations, time_log)))
+}
+
+/// Wraps `task`, if there is one, in a TaskView that includes its annotations and time log.
+fn detailed_task_view(
+    task: Option<DetailedTask>,
+    coefficients: &UrgencyCoefficients,
+) -> Option<Json<TaskView>> {
+    task.map(|((task, relations), annotations, time_log)| {
+        Json(
+            TaskView::new(task, relations, coefficients)
+                .with_annotations(annotations)
+                .with_time_log(time_log),
+        )
+    })
+}
+
+/// Adds an ETag header with the task's version to `task_view`.
+fn with_etag(task_view: Json<TaskView>) -> WithETag<Json<TaskView>> {
+    WithETag {
+        version: task_view.task.version,
+        inner: task_view,
+    }
+}
+
+/// The response's ETag header holds the task's version, which requests that change the task
+/// have to send back in an If-Match header.
 #[get("/task/<task_id>")]
 pub async fn get_task_by_id(
     db: DBConn,
     task_id: i32,
-    _token: ApiToken,
-) -> Result<Option<Json<Task>>> {
-    db.run(move |conn| db_queries::get_task_by_id(conn, task_id, true))
-        .await
-        .map(|row| row.map(Json))
-        .map_err(RaskApiError::DatabaseError)
+    coefficients: &State<UrgencyCoefficients>,
+    token: ApiToken,
+) -> Result<Option<WithETag<Json<TaskView>>>> {
+    db.run(move |conn| {
+        let task = db_queries::get_task_by_id(conn, token.user.id, task_id, true)?;
+        with_details(conn, task)
+    })
+    .await
+    .map(|row| detailed_task_view(row, coefficients).map(with_etag))
+    .map_err(RaskApiError::DatabaseError)
 }
 
-#[get("/tasks/all")]
-pub async fn get_tasks(db: DBConn, _token: ApiToken) -> Result<Json<Vec<Task>>> {
-    let tasks = db.run(move |conn| db_queries::get_tasks(conn)).await?;
+#[get("/task/display/<display_id>")]
+pub async fn get_task_by_display_id(
+    db: DBConn,
+    display_id: i32,
+    coefficients: &State<UrgencyCoefficients>,
+    token: ApiToken,
+) -> Result<Option<WithETag<Json<TaskView>>>> {
+    db.run(move |conn| {
+        let task = db_queries::get_task_by_display_id(conn, token.user.id, display_id)?;
+        with_details(conn, task)
+    })
+    .await
+    .map(|row| detailed_task_view(row, coefficients).map(with_etag))
+    .map_err(RaskApiError::DatabaseError)
+}
 
-    Ok(Json(tasks))
+/// Parses the client's `YYYY-MM-DD` date, which decides which tasks are still waiting. Defaults
+/// to today's date in UTC.
+fn parse_today(today: Option<String>) -> Result<NaiveDate> {
+    match today {
+        Some(today) => NaiveDate::parse_from_str(&today, "%Y-%m-%d")
+            .map_err(|_| RaskApiError::InvalidDate(today)),
+        None => Ok(Utc::today().naive_utc()),
+    }
 }
 
-#[get("/tasks/alive")]
-pub async fn get_alive_tasks(db: DBConn, _token: ApiToken) -> Result<Json<Vec<Task>>> {
+/// Only returns tasks that match every filter in `filter`, e.g. `project:work` or `+errand`.
+/// See `rask_lib::filter` for the syntax. Deleted tasks are left out unless `include_deleted`
+/// is true or one of the filters is a `mode:` filter. See `parse_today()` for `today`.
+#[get("/tasks?<filter>&<include_deleted>&<today>")]
+pub async fn get_filtered_tasks(
+    db: DBConn,
+    filter: Vec<String>,
+    include_deleted: Option<bool>,
+    today: Option<String>,
+    coefficients: &State<UrgencyCoefficients>,
+    token: ApiToken,
+) -> Result<Json<Vec<TaskView>>> {
+    let filters = filter
+        .iter()
+        .map(|f| f.parse())
+        .collect::<Result<Vec<Filter>, _>>()?;
+    let include_deleted = include_deleted.unwrap_or(false);
+    let today = parse_today(today)?;
+
     let tasks = db
-        .run(move |conn| db_queries::get_alive_tasks(conn))
+        .run(move |conn| {
+            let tasks = db_queries::get_filtered_tasks(
+                conn,
+                token.user.id,
+                &filters,
+                include_deleted,
+                today,
+            )?;
+            db_queries::with_relations(conn, tasks)
+        })
         .await?;
 
-    Ok(Json(tasks))
+    Ok(Json(task_views(tasks, coefficients)))
+}
+
+/// Parses an RFC 3339 timestamp, or a `YYYY-MM-DD` date, which means midnight UTC.
+fn parse_timestamp(timestamp: &str) -> Result<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(timestamp, "%Y-%m-%d")
+                .map(|date| DateTime::from_utc(date.and_hms(0, 0, 0), Utc))
+        })
+        .map_err(|_| RaskApiError::InvalidTimestamp(timestamp.to_string()))
+}
+
+/// Tasks that were completed at or after `since` and before `until`, if they're given, in the
+/// order that they were completed. See `parse_timestamp()` for their format.
+#[get("/tasks/completed?<since>&<until>")]
+pub async fn get_completed_tasks(
+    db: DBConn,
+    since: Option<String>,
+    until: Option<String>,
+    coefficients: &State<UrgencyCoefficients>,
+    token: ApiToken,
+) -> Result<Json<Vec<TaskView>>> {
+    let since = since.as_deref().map(parse_timestamp).transpose()?;
+    let until = until.as_deref().map(parse_timestamp).transpose()?;
+
+    let tasks = db
+        .run(move |conn| {
+            let tasks = db_queries::get_completed_tasks(conn, token.user.id, since, until)?;
+            db_queries::with_relations(conn, tasks)
+        })
+        .await?;
+
+    Ok(Json(task_views(tasks, coefficients)))
+}
+
+/// Deleted tasks are only included if `include_deleted` is true.
+#[get("/tasks/all?<include_deleted>")]
+pub async fn get_tasks(
+    db: DBConn,
+    include_deleted: Option<bool>,
+    coefficients: &State<UrgencyCoefficients>,
+    token: ApiToken,
+) -> Result<Json<Vec<TaskView>>> {
+    let include_deleted = include_deleted.unwrap_or(false);
+    let tasks = db
+        .run(move |conn| {
+            let tasks = db_queries::get_tasks(conn, token.user.id, include_deleted)?;
+            db_queries::with_relations(conn, tasks)
+        })
+        .await?;
+
+    Ok(Json(task_views(tasks, coefficients)))
+}
+
+/// Only returns tasks that have every tag in `tag` and none of the tags in `without_tag`. See
+/// `parse_today()` for `today`.
+#[get("/tasks/alive?<tag>&<without_tag>&<today>")]
+pub async fn get_alive_tasks(
+    db: DBConn,
+    tag: Vec<String>,
+    without_tag: Vec<String>,
+    today: Option<String>,
+    coefficients: &State<UrgencyCoefficients>,
+    token: ApiToken,
+) -> Result<Json<Vec<TaskView>>> {
+    let today = parse_today(today)?;
+    let tasks = db
+        .run(move |conn| {
+            let tasks =
+                db_queries::get_alive_tasks(conn, token.user.id, &tag, &without_tag, today)?;
+            db_queries::with_relations(conn, tasks)
+        })
+        .await?;
+
+    Ok(Json(task_views(tasks, coefficients)))
+}
+
+/// See `parse_today()` for `today`.
+#[get("/tasks/waiting?<today>")]
+pub async fn get_waiting_tasks(
+    db: DBConn,
+    today: Option<String>,
+    coefficients: &State<UrgencyCoefficients>,
+    token: ApiToken,
+) -> Result<Json<Vec<TaskView>>> {
+    let today = parse_today(today)?;
+    let tasks = db
+        .run(move |conn| {
+            let tasks = db_queries::get_waiting_tasks(conn, token.user.id, today)?;
+            db_queries::with_relations(conn, tasks)
+        })
+        .await?;
+
+    Ok(Json(task_views(tasks, coefficients)))
 }
 
 #[post("/task", data = "<task_form>")]
 pub async fn create_task(
     db: DBConn,
-    task_form: Form<TaskForm>,
-    _token: ApiToken,
-) -> Result<Created<Json<Task>>> {
-    let new_task = db
-        .run(move |conn| db_queries::create_task(conn, WrappedNewTask::from(task_form).0))
+    task_form: Result<FormOrJson<TaskForm>, InvalidBody>,
+    coefficients: &State<UrgencyCoefficients>,
+    token: ApiToken,
+) -> Result<Created<Json<TaskView>>> {
+    let mut task_form = task_form?;
+    let tags = std::mem::take(&mut task_form.0.tags);
+    let new_task = WrappedNewTask::from(task_form).0;
+    let (new_task, relations) = db
+        .run(move |conn| {
+            conn.transaction(|| {
+                let task = db_queries::create_task(
+                    conn,
+                    token.user.id,
+                    new_task,
+                    &tags,
+                    &token.token_hash,
+                )?;
+                db_queries::maybe_with_relations(conn, Some(task))
+            })
+        })
+        .await?
+        .expect("the new task exists");
+
+    Ok(
+        Created::new(format!("/task/{}", new_task.id)).body(Json(TaskView::new(
+            new_task,
+            relations,
+            coefficients,
+        ))),
+    )
+}
+
+/// Creates tasks and recurrence templates from a JSON array of tasks that were exported from
+/// Taskwarrior; see `taskwarrior::prepare_import()`. Taskwarrior's dates are UTC timestamps of the
+/// user's local midnights, so they're converted at `utc_offset`, the user's offset from UTC in
+/// seconds, which defaults to 0. Nothing is imported if any task is invalid.
+#[post("/tasks/import?<utc_offset>", data = "<tasks>")]
+pub async fn import_tasks(
+    db: DBConn,
+    utc_offset: Option<String>,
+    tasks: Result<TaskwarriorTasks, InvalidBody>,
+    coefficients: &State<UrgencyCoefficients>,
+    token: ApiToken,
+) -> Result<Created<Json<ImportedRows>>> {
+    let utc_offset = match utc_offset {
+        Some(utc_offset) => utc_offset
+            .parse()
+            .ok()
+            .and_then(FixedOffset::east_opt)
+            .ok_or(RaskApiError::InvalidUtcOffset(utc_offset))?,
+        None => FixedOffset::east(0),
+    };
+    let import = taskwarrior::prepare_import(tasks?.0, utc_offset).map_err(InvalidBody::from)?;
+
+    let (tasks, recurrences) = db
+        .run(move |conn| {
+            let (tasks, recurrences) =
+                db_queries::import_taskwarrior(conn, token.user.id, import, &token.token_hash)?;
+            Ok::<_, diesel::result::Error>((db_queries::with_relations(conn, tasks)?, recurrences))
+        })
         .await?;
 
-    Ok(Created::new(format!("/task/{}", new_task.id)).body(Json(new_task)))
+    Ok(Created::new("/tasks/all").body(Json(ImportedRows {
+        tasks: task_views(tasks, coefficients),
+        recurrences,
+    })))
 }
 
 #[post("/task/<task_id>/complete")]
 pub async fn complete_task(
     db: DBConn,
     task_id: i32,
-    _token: ApiToken,
-) -> Result<Option<Json<Task>>> {
-    db.run(move |conn| db_queries::update_mode(conn, task_id, MODE_COMPLETED))
-        .await
-        .map(|row| row.map(Json))
-        .map_err(RaskApiError::DatabaseError)
+    coefficients: &State<UrgencyCoefficients>,
+    token: ApiToken,
+    if_match: IfMatch,
+) -> Result<Option<Json<TaskView>>> {
+    db.run(move |conn| {
+        conn.transaction(|| {
+            check_version(
+                &if_match,
+                db_queries::lock_task_version(conn, token.user.id, task_id)?,
+            )?;
+
+            let task = db_queries::update_mode(
+                conn,
+                token.user.id,
+                task_id,
+                Mode::Completed,
+                &token.token_hash,
+            )?;
+            Ok(db_queries::maybe_with_relations(conn, task)?)
+        })
+    })
+    .await
+    .map(|row| task_view(row, coefficients))
 }
 
 #[post("/task/<task_id>/uncomplete")]
 pub async fn uncomplete_task(
     db: DBConn,
     task_id: i32,
-    _token: ApiToken,
-) -> Result<Option<Json<Task>>> {
-    db.run(move |conn| db_queries::uncomplete_task(conn, task_id))
-        .await
-        .map(|row| row.map(Json))
-        .map_err(RaskApiError::DatabaseError)
+    coefficients: &State<UrgencyCoefficients>,
+    token: ApiToken,
+) -> Result<Option<Json<TaskView>>> {
+    db.run(move |conn| {
+        let task = db_queries::uncomplete_task(conn, token.user.id, task_id, &token.token_hash)?;
+        db_queries::maybe_with_relations(conn, task)
+    })
+    .await
+    .map(|row| task_view(row, coefficients))
+    .map_err(RaskApiError::DatabaseError)
+}
+
+/// Marks a pending task active, and starts tracking the time that's spent on it.
+#[post("/task/<task_id>/start")]
+pub async fn start_task(
+    db: DBConn,
+    task_id: i32,
+    coefficients: &State<UrgencyCoefficients>,
+    token: ApiToken,
+) -> Result<Option<Json<TaskView>>> {
+    db.run(move |conn| {
+        let task = db_queries::start_task(conn, token.user.id, task_id, &token.token_hash)?;
+        db_queries::maybe_with_relations(conn, task)
+    })
+    .await
+    .map(|row| task_view(row, coefficients))
+    .map_err(RaskApiError::DatabaseError)
+}
+
+/// Marks an active task pending again, and stops tracking the time that's spent on it.
+#[post("/task/<task_id>/stop")]
+pub async fn stop_task(
+    db: DBConn,
+    task_id: i32,
+    coefficients: &State<UrgencyCoefficients>,
+    token: ApiToken,
+) -> Result<Option<Json<TaskView>>> {
+    db.run(move |conn| {
+        let task = db_queries::stop_task(conn, token.user.id, task_id, &token.token_hash)?;
+        db_queries::maybe_with_relations(conn, task)
+    })
+    .await
+    .map(|row| task_view(row, coefficients))
+    .map_err(RaskApiError::DatabaseError)
+}
+
+#[post("/task/<task_id>/delete")]
+pub async fn delete_task(
+    db: DBConn,
+    task_id: i32,
+    coefficients: &State<UrgencyCoefficients>,
+    token: ApiToken,
+) -> Result<Option<Json<TaskView>>> {
+    db.run(move |conn| {
+        let task = db_queries::delete_task(conn, token.user.id, task_id, &token.token_hash)?;
+        db_queries::maybe_with_relations(conn, task)
+    })
+    .await
+    .map(|row| task_view(row, coefficients))
+    .map_err(RaskApiError::DatabaseError)
+}
+
+#[post("/task/<task_id>/undelete")]
+pub async fn undelete_task(
+    db: DBConn,
+    task_id: i32,
+    coefficients: &State<UrgencyCoefficients>,
+    token: ApiToken,
+) -> Result<Option<Json<TaskView>>> {
+    db.run(move |conn| {
+        let task = db_queries::undelete_task(conn, token.user.id, task_id, &token.token_hash)?;
+        db_queries::maybe_with_relations(conn, task)
+    })
+    .await
+    .map(|row| task_view(row, coefficients))
+    .map_err(RaskApiError::DatabaseError)
 }
 
 #[post("/task/<task_id>/modify", data = "<task_form>")]
 pub async fn modify_task(
     db: DBConn,
     task_id: i32,
-    task_form: Form<TaskForm>,
-    _token: ApiToken,
-) -> Result<Option<Json<Task>>> {
-    db.run(move |conn| db_queries::update_task(conn, task_id, WrappedNewTask::from(task_form).0))
+    task_form: Result<FormOrJson<TaskForm>, InvalidBody>,
+    coefficients: &State<UrgencyCoefficients>,
+    token: ApiToken,
+    if_match: IfMatch,
+) -> Result<Option<Json<TaskView>>> {
+    let new_task = WrappedNewTask::from(task_form?).0;
+    db.run(move |conn| {
+        conn.transaction(|| {
+            check_version(
+                &if_match,
+                db_queries::lock_task_version(conn, token.user.id, task_id)?,
+            )?;
+
+            let task =
+                db_queries::update_task(conn, token.user.id, task_id, new_task, &token.token_hash)?;
+            Ok(db_queries::maybe_with_relations(conn, task)?)
+        })
+    })
+    .await
+    .map(|row| task_view(row, coefficients))
+}
+
+/// Changes only the fields that are in the JSON request body; fields that are null are cleared.
+#[patch("/task/<task_id>", data = "<patch>")]
+pub async fn patch_task(
+    db: DBConn,
+    task_id: i32,
+    patch: Result<JsonObject<TaskPatch>, InvalidBody>,
+    coefficients: &State<UrgencyCoefficients>,
+    token: ApiToken,
+    if_match: IfMatch,
+) -> Result<Option<Json<TaskView>>> {
+    let JsonObject(patch) = patch?;
+    db.run(move |conn| {
+        conn.transaction(|| {
+            check_version(
+                &if_match,
+                db_queries::lock_task_version(conn, token.user.id, task_id)?,
+            )?;
+
+            let task =
+                db_queries::patch_task(conn, token.user.id, task_id, patch, &token.token_hash)?;
+            Ok(db_queries::maybe_with_relations(conn, task)?)
+        })
+    })
+    .await
+    .map(|row| task_view(row, coefficients))
+}
+
+#[post("/task/<task_id>/tag", data = "<tag_form>")]
+pub async fn tag_task(
+    db: DBConn,
+    task_id: i32,
+    tag_form: Form<TagForm>,
+    coefficients: &State<UrgencyCoefficients>,
+    token: ApiToken,
+) -> Result<Option<Json<TaskView>>> {
+    let tag_name = tag_form.into_inner().name;
+    db.run(move |conn| {
+        let task = db_queries::tag_task(conn, token.user.id, task_id, tag_name, &token.token_hash)?;
+        db_queries::maybe_with_relations(conn, task)
+    })
+    .await
+    .map(|row| task_view(row, coefficients))
+    .map_err(RaskApiError::DatabaseError)
+}
+
+#[post("/task/<task_id>/untag", data = "<tag_form>")]
+pub async fn untag_task(
+    db: DBConn,
+    task_id: i32,
+    tag_form: Form<TagForm>,
+    coefficients: &State<UrgencyCoefficients>,
+    token: ApiToken,
+) -> Result<Option<Json<TaskView>>> {
+    let tag_name = tag_form.into_inner().name;
+    db.run(move |conn| {
+        let task =
+            db_queries::untag_task(conn, token.user.id, task_id, tag_name, &token.token_hash)?;
+        db_queries::maybe_with_relations(conn, task)
+    })
+    .await
+    .map(|row| task_view(row, coefficients))
+    .map_err(RaskApiError::DatabaseError)
+}
+
+#[post("/task/<task_id>/depend", data = "<dependency_form>")]
+pub async fn add_dependency(
+    db: DBConn,
+    task_id: i32,
+    dependency_form: Form<DependencyForm>,
+    coefficients: &State<UrgencyCoefficients>,
+    token: ApiToken,
+) -> Result<Option<Json<TaskView>>> {
+    let depends_on_id = dependency_form.into_inner().depends_on;
+    db.run(move |conn| {
+        conn.transaction(|| {
+            // Other users' tasks 404 before they're checked for cycles, so that their
+            // dependencies can't be probed.
+            if !db_queries::owns_tasks(conn, token.user.id, &[task_id, depends_on_id])? {
+                return Ok(None);
+            }
+
+            if db_queries::would_create_cycle(conn, task_id, depends_on_id)? {
+                return Err(RaskApiError::DependencyCycle {
+                    task_id,
+                    depends_on_id,
+                });
+            }
+
+            let task = db_queries::add_dependency(conn, token.user.id, task_id, depends_on_id)?;
+            Ok(db_queries::maybe_with_relations(conn, task)?)
+        })
+    })
+    .await
+    .map(|row| task_view(row, coefficients))
+}
+
+#[post("/task/<task_id>/undepend", data = "<dependency_form>")]
+pub async fn remove_dependency(
+    db: DBConn,
+    task_id: i32,
+    dependency_form: Form<DependencyForm>,
+    coefficients: &State<UrgencyCoefficients>,
+    token: ApiToken,
+) -> Result<Option<Json<TaskView>>> {
+    let depends_on_id = dependency_form.into_inner().depends_on;
+    db.run(move |conn| {
+        let task = db_queries::remove_dependency(conn, token.user.id, task_id, depends_on_id)?;
+        db_queries::maybe_with_relations(conn, task)
+    })
+    .await
+    .map(|row| task_view(row, coefficients))
+    .map_err(RaskApiError::DatabaseError)
+}
+
+#[post("/task/<task_id>/annotate", data = "<annotation_form>")]
+pub async fn annotate_task(
+    db: DBConn,
+    task_id: i32,
+    annotation_form: Form<AnnotationForm>,
+    token: ApiToken,
+) -> Result<Option<Created<Json<TaskAnnotation>>>> {
+    let body = annotation_form.into_inner().body;
+    let annotation = db
+        .run(move |conn| db_queries::annotate_task(conn, token.user.id, task_id, body))
+        .await?;
+
+    Ok(annotation.map(|annotation| {
+        Created::new(format!("/task/{}", annotation.task_id)).body(Json(annotation))
+    }))
+}
+
+#[post("/task/<task_id>/annotation/<annotation_id>/delete")]
+pub async fn remove_annotation(
+    db: DBConn,
+    task_id: i32,
+    annotation_id: i32,
+    token: ApiToken,
+) -> Result<Option<Json<TaskAnnotation>>> {
+    db.run(move |conn| db_queries::remove_annotation(conn, token.user.id, task_id, annotation_id))
         .await
         .map(|row| row.map(Json))
         .map_err(RaskApiError::DatabaseError)
 }
 
+/// Renumbers alive tasks' display IDs so that they run from 1 to N.
+#[post("/gc")]
+pub async fn gc(
+    db: DBConn,
+    coefficients: &State<UrgencyCoefficients>,
+    token: ApiToken,
+) -> Result<Json<Vec<TaskView>>> {
+    let tasks = db
+        .run(move |conn| {
+            let tasks = db_queries::compact_display_ids(conn, token.user.id)?;
+            db_queries::with_relations(conn, tasks)
+        })
+        .await?;
+
+    Ok(Json(task_views(tasks, coefficients)))
+}
+
 // Recurrences
 
+/// Like `get_task_by_id()`, the response's ETag header holds the recurrence's version.
 #[get("/recurrence/<recurrence_id>")]
 pub async fn get_recurrence_by_id(
     db: DBConn,
     recurrence_id: i32,
-    _token: ApiToken,
-) -> Result<Option<Json<RecurrenceTemplate>>> {
-    db.run(move |conn| db_queries::get_recurrence_by_id(conn, recurrence_id))
+    token: ApiToken,
+) -> Result<Option<WithETag<Json<RecurrenceTemplate>>>> {
+    db.run(move |conn| db_queries::get_recurrence_by_id(conn, token.user.id, recurrence_id))
         .await
-        .map(|row| row.map(Json))
+        .map(|row| {
+            row.map(|recurrence| WithETag {
+                version: recurrence.version,
+                inner: Json(recurrence),
+            })
+        })
         .map_err(RaskApiError::DatabaseError)
 }
 
 #[post("/recurrence", data = "<recurrence_form>")]
 pub async fn create_recurrence(
     db: DBConn,
-    recurrence_form: Form<RecurrenceForm>,
-    _token: ApiToken,
+    recurrence_form: Result<FormOrJson<RecurrenceForm>, InvalidBody>,
+    token: ApiToken,
 ) -> Result<Created<Json<RecurrenceTemplate>>> {
+    let new_template = WrappedNewRecurrenceTemplate::from(recurrence_form?).0;
     let new_template = db
         .run(move |conn| {
-            db_queries::create_recurrence(
-                conn,
-                WrappedNewRecurrenceTemplate::from(recurrence_form).0,
-            )
+            db_queries::create_recurrence(conn, token.user.id, new_template, &token.token_hash)
         })
         .await?;
 
@@ -150,12 +777,9 @@ pub async fn create_recurrence(
 }
 
 #[get("/recurrences/all")]
-pub async fn get_recurrences(
-    db: DBConn,
-    _token: ApiToken,
-) -> Result<Json<Vec<RecurrenceTemplate>>> {
+pub async fn get_recurrences(db: DBConn, token: ApiToken) -> Result<Json<Vec<RecurrenceTemplate>>> {
     let recurrences = db
-        .run(move |conn| db_queries::get_recurrences(conn))
+        .run(move |conn| db_queries::get_recurrences(conn, token.user.id))
         .await?;
 
     Ok(Json(recurrences))
@@ -165,19 +789,109 @@ pub async fn get_recurrences(
 pub async fn modify_recurrence(
     db: DBConn,
     recurrence_id: i32,
-    recurrence_form: Form<RecurrenceForm>,
-    _token: ApiToken,
+    recurrence_form: Result<FormOrJson<RecurrenceForm>, InvalidBody>,
+    token: ApiToken,
+    if_match: IfMatch,
 ) -> Result<Option<Json<RecurrenceTemplate>>> {
+    let new_template = WrappedNewRecurrenceTemplate::from(recurrence_form?).0;
     db.run(move |conn| {
-        db_queries::update_recurrence(
-            conn,
-            recurrence_id,
-            WrappedNewRecurrenceTemplate::from(recurrence_form).0,
-        )
+        conn.transaction(|| {
+            check_version(
+                &if_match,
+                db_queries::lock_recurrence_version(conn, token.user.id, recurrence_id)?,
+            )?;
+
+            Ok(db_queries::update_recurrence(
+                conn,
+                token.user.id,
+                recurrence_id,
+                new_template,
+                &token.token_hash,
+            )?)
+        })
+    })
+    .await
+    .map(|row| row.map(Json))
+}
+
+/// Like `patch_task()`, but for recurrence templates.
+#[patch("/recurrence/<recurrence_id>", data = "<changes>")]
+pub async fn patch_recurrence(
+    db: DBConn,
+    recurrence_id: i32,
+    changes: Result<JsonObject<RecurrenceChanges>, InvalidBody>,
+    token: ApiToken,
+    if_match: IfMatch,
+) -> Result<Option<Json<RecurrenceTemplate>>> {
+    let JsonObject(changes) = changes?;
+    db.run(move |conn| {
+        conn.transaction(|| {
+            check_version(
+                &if_match,
+                db_queries::lock_recurrence_version(conn, token.user.id, recurrence_id)?,
+            )?;
+
+            Ok(db_queries::patch_recurrence(
+                conn,
+                token.user.id,
+                recurrence_id,
+                changes,
+                &token.token_hash,
+            )?)
+        })
+    })
+    .await
+    .map(|row| row.map(Json))
+}
+
+// Time log
+
+/// The total time that's been spent on each project's tasks.
+#[get("/time/projects")]
+pub async fn get_project_times(db: DBConn, token: ApiToken) -> Result<Json<Vec<ProjectTime>>> {
+    db.run(move |conn| db_queries::get_project_times(conn, token.user.id))
+        .await
+        .map(Json)
+        .map_err(RaskApiError::DatabaseError)
+}
+
+// History
+
+#[get("/history/latest")]
+pub async fn get_latest_change(db: DBConn, token: ApiToken) -> Result<Option<Json<TaskHistory>>> {
+    db.run(move |conn| db_queries::get_latest_change(conn, token.user.id))
+        .await
+        .map(|row| row.map(Json))
+        .map_err(RaskApiError::DatabaseError)
+}
+
+/// Reverts the change with the ID `history_id`, as returned by `/history/latest`. Only the most
+/// recent change can be undone, so that a client doesn't undo a change that it hasn't seen.
+#[post("/undo/<history_id>")]
+pub async fn undo(
+    db: DBConn,
+    history_id: i32,
+    token: ApiToken,
+) -> Result<Option<Json<TaskHistory>>> {
+    db.run(move |conn| {
+        conn.transaction(|| {
+            let change = match db_queries::lock_latest_change(conn, token.user.id)? {
+                Some(change) if change.id == history_id => change,
+                Some(latest) => {
+                    return Err(RaskApiError::NotLatestChange {
+                        history_id,
+                        latest_id: latest.id,
+                    })
+                }
+                None => return Ok(None),
+            };
+
+            db_queries::undo_change(conn, &change)?;
+            Ok(Some(change))
+        })
     })
     .await
     .map(|row| row.map(Json))
-    .map_err(RaskApiError::DatabaseError)
 }
 
 // Misc
@@ -1,34 +1,46 @@
 use chrono::NaiveDate;
 use rask_lib::models::{
-    NewRecurrenceTemplate, NewTask, PRIORITY_HIGH, PRIORITY_LOW, PRIORITY_MEDIUM,
+    FieldError, NewRecurrenceTemplate, NewTask, Priority, RecurrenceChanges, TaskChanges, TaskPatch,
 };
+use rask_lib::taskwarrior::{TaskwarriorError, TaskwarriorTask};
+use rocket::data::{self, Data, FromData};
 use rocket::form::{self, ValueField};
 use rocket::form::{Form, FromForm, FromFormField};
+use rocket::http::Status;
+use rocket::outcome::Outcome;
+use rocket::serde::json::{self, Json};
+use rocket::Request;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+const INVALID_PROJECT: &str = "project must be a single word or blank";
+const INVALID_DATE: &str = "invalid date";
+const INVALID_TAG: &str = "tag must be a single word that starts with a letter";
 
 /// Task projects must be a single word or None.
 fn validate_project<'v>(project: &Option<String>) -> form::Result<'v, ()> {
     match project {
         Some(project) if project.split(' ').count() != 1 => {
-            Err(form::Error::validation("project must be a single word or blank").into())
+            Err(form::Error::validation(INVALID_PROJECT).into())
         }
         _ => Ok(()),
     }
 }
 
-/// Task priorities must be a valid Priority value or None.
-fn validate_priority<'v>(priority: &Option<String>) -> form::Result<'v, ()> {
-    match priority {
-        None => Ok(()),
-        Some(priority_str)
-            if [PRIORITY_HIGH.0, PRIORITY_MEDIUM.0, PRIORITY_LOW.0]
-                .iter()
-                .any(|&v| v == priority_str) =>
-        {
-            Ok(())
-        }
-        _ => Err(form::Error::validation("priority must be one of H,M,L or blank").into()),
+/// Tag names must be a single word that starts with a letter, so that they can't be mistaken
+/// for anything else in `+tag`/`-tag` syntax.
+fn validate_tag_name<'v>(name: &str) -> form::Result<'v, ()> {
+    if !name.starts_with(char::is_alphabetic) || name.contains(char::is_whitespace) {
+        Err(form::Error::validation(INVALID_TAG).into())
+    } else {
+        Ok(())
     }
 }
+
+fn validate_tag_names<'v>(names: &[String]) -> form::Result<'v, ()> {
+    names.iter().try_for_each(|name| validate_tag_name(name))
+}
+
 pub struct NaiveDateFormField(NaiveDate);
 
 #[rocket::async_trait]
@@ -38,19 +50,70 @@ impl<'r> FromFormField<'r> for NaiveDateFormField {
 
         match parsed {
             Ok(naive_date_time) => Ok(NaiveDateFormField(naive_date_time)),
-            Err(_) => Err(form::Error::validation("invalid date").into()),
+            Err(_) => Err(form::Error::validation(INVALID_DATE).into()),
         }
     }
 }
 
+/// A date that's None if the field is missing. Like PriorityFormField, this gives a 422 for an
+/// invalid date rather than silently dropping it.
+pub struct OptionalDateFormField(Option<NaiveDate>);
+
+#[rocket::async_trait]
+impl<'r> FromFormField<'r> for OptionalDateFormField {
+    fn from_value(form_value: ValueField<'r>) -> form::Result<'r, Self> {
+        NaiveDateFormField::from_value(form_value).map(|date| OptionalDateFormField(Some(date.0)))
+    }
+
+    fn default() -> Option<Self> {
+        Some(OptionalDateFormField(None))
+    }
+}
+
+/// A priority that's None if the field is missing. Unlike an `Option<Priority>` field, which
+/// would silently drop an invalid priority, this gives a 422.
+pub struct PriorityFormField(Option<Priority>);
+
+#[rocket::async_trait]
+impl<'r> FromFormField<'r> for PriorityFormField {
+    fn from_value(form_value: ValueField<'r>) -> form::Result<'r, Self> {
+        Priority::from_value(form_value).map(|priority| PriorityFormField(Some(priority)))
+    }
+
+    fn default() -> Option<Self> {
+        Some(PriorityFormField(None))
+    }
+}
+
 #[derive(FromForm)]
 pub struct TaskForm {
     name: String,
     #[field(validate=validate_project())]
     project: Option<String>,
-    #[field(validate=validate_priority())]
-    priority: Option<String>,
-    due: Option<NaiveDateFormField>,
+    priority: PriorityFormField,
+    due: OptionalDateFormField,
+    wait: OptionalDateFormField,
+    /// The names of the tags to give a new task, e.g. `tags=errand&tags=car`. Modifying a task
+    /// leaves its tags alone.
+    #[field(validate=validate_tag_names())]
+    pub tags: Vec<String>,
+}
+
+#[derive(FromForm)]
+pub struct AnnotationForm {
+    #[field(validate=len(1..))]
+    pub body: String,
+}
+
+#[derive(FromForm)]
+pub struct TagForm {
+    #[field(validate=validate_tag_name())]
+    pub name: String,
+}
+
+#[derive(FromForm)]
+pub struct DependencyForm {
+    pub depends_on: i32,
 }
 
 #[derive(FromForm)]
@@ -58,36 +121,434 @@ pub struct RecurrenceForm {
     name: String,
     #[field(validate=validate_project())]
     project: Option<String>,
-    #[field(validate=validate_priority())]
-    priority: Option<String>,
+    priority: PriorityFormField,
     due: NaiveDateFormField,
     days_between_recurrences: i32,
 }
 
+/// Why a request body couldn't be read. `errors` is empty if the body as a whole was unreadable,
+/// e.g. because it wasn't valid JSON.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidBody {
+    pub message: String,
+    pub errors: Vec<FieldError>,
+}
+
+impl InvalidBody {
+    fn new(message: impl ToString) -> Self {
+        InvalidBody {
+            message: message.to_string(),
+            errors: vec![],
+        }
+    }
+
+    fn with_field_errors(errors: Vec<FieldError>) -> Self {
+        InvalidBody {
+            message: "Some fields are missing or invalid".to_string(),
+            errors,
+        }
+    }
+}
+
+impl From<form::Errors<'_>> for InvalidBody {
+    fn from(errors: form::Errors<'_>) -> Self {
+        InvalidBody::with_field_errors(
+            errors
+                .iter()
+                .map(|error| FieldError {
+                    field: error
+                        .name
+                        .as_ref()
+                        .map(|name| name.to_string())
+                        .unwrap_or_default(),
+                    message: error.to_string(),
+                })
+                .collect(),
+        )
+    }
+}
+
+/// The fields of a JSON object, which are removed as they're read. Missing and invalid fields
+/// are recorded in `errors`.
+pub struct JsonFields {
+    object: Map<String, Value>,
+    errors: Vec<FieldError>,
+}
+
+impl JsonFields {
+    /// Parses the field named `field` with `parse`, or returns None if it's missing or null.
+    fn optional<T>(
+        &mut self,
+        field: &str,
+        parse: impl FnOnce(Value) -> Result<T, String>,
+    ) -> Option<T> {
+        match self.object.remove(field) {
+            None | Some(Value::Null) => None,
+            Some(value) => match parse(value) {
+                Ok(value) => Some(value),
+                Err(message) => {
+                    self.errors.push(FieldError {
+                        field: field.to_string(),
+                        message,
+                    });
+                    None
+                }
+            },
+        }
+    }
+
+    /// Like `optional()`, but a missing or null field is an error.
+    fn required<T>(
+        &mut self,
+        field: &str,
+        parse: impl FnOnce(Value) -> Result<T, String>,
+    ) -> Option<T> {
+        if matches!(self.object.get(field), None | Some(Value::Null)) {
+            self.errors.push(FieldError {
+                field: field.to_string(),
+                message: "missing".to_string(),
+            });
+        }
+        self.optional(field, parse)
+    }
+
+    /// For partial updates: returns None if the field is missing, or Some(None) if it's null.
+    fn nullable<T>(
+        &mut self,
+        field: &str,
+        parse: impl FnOnce(Value) -> Result<T, String>,
+    ) -> Option<Option<T>> {
+        match self.object.get(field) {
+            None => None,
+            Some(Value::Null) => Some(None),
+            Some(_) => self.optional(field, parse).map(Some),
+        }
+    }
+
+    /// For partial updates of fields that can't be cleared: like `optional()`, but null is an
+    /// error.
+    fn non_null<T>(
+        &mut self,
+        field: &str,
+        parse: impl FnOnce(Value) -> Result<T, String>,
+    ) -> Option<T> {
+        if let Some(Value::Null) = self.object.get(field) {
+            self.errors.push(FieldError {
+                field: field.to_string(),
+                message: "can't be null".to_string(),
+            });
+        }
+        self.optional(field, parse)
+    }
+}
+
+fn parse_json<T: DeserializeOwned>(value: Value) -> Result<T, String> {
+    serde_json::from_value(value).map_err(|e| e.to_string())
+}
+
+fn parse_json_project(value: Value) -> Result<String, String> {
+    let project: String = parse_json(value)?;
+    if project.split(' ').count() == 1 {
+        Ok(project)
+    } else {
+        Err(INVALID_PROJECT.to_string())
+    }
+}
+
+fn parse_json_priority(value: Value) -> Result<Priority, String> {
+    parse_json::<String>(value)?
+        .parse()
+        .map_err(|e: rask_lib::models::ModelParseError| e.to_string())
+}
+
+fn parse_json_tags(value: Value) -> Result<Vec<String>, String> {
+    let tags: Vec<String> = parse_json(value)?;
+    validate_tag_names(&tags).map_err(|_| INVALID_TAG.to_string())?;
+    Ok(tags)
+}
+
+fn parse_json_date(value: Value) -> Result<NaiveDateFormField, String> {
+    NaiveDate::parse_from_str(&parse_json::<String>(value)?, "%Y-%m-%d")
+        .map(NaiveDateFormField)
+        .map_err(|_| INVALID_DATE.to_string())
+}
+
+/// A form that can also be read from the fields of a JSON object.
+pub trait FromJson: Sized {
+    /// Returns None if any fields were missing or invalid; each one is recorded in `fields`.
+    fn from_json(fields: &mut JsonFields) -> Option<Self>;
+}
+
+impl FromJson for TaskForm {
+    fn from_json(fields: &mut JsonFields) -> Option<Self> {
+        let name = fields.required("name", parse_json);
+        let project = fields.optional("project", parse_json_project);
+        let priority = fields.optional("priority", parse_json_priority);
+        let due = fields.optional("due", parse_json_date);
+        let wait = fields.optional("wait", parse_json_date);
+        let tags = fields.optional("tags", parse_json_tags);
+
+        Some(TaskForm {
+            name: name?,
+            project,
+            priority: PriorityFormField(priority),
+            due: OptionalDateFormField(due.map(|due| due.0)),
+            wait: OptionalDateFormField(wait.map(|wait| wait.0)),
+            tags: tags.unwrap_or_default(),
+        })
+    }
+}
+
+impl FromJson for RecurrenceForm {
+    fn from_json(fields: &mut JsonFields) -> Option<Self> {
+        let name = fields.required("name", parse_json);
+        let project = fields.optional("project", parse_json_project);
+        let priority = fields.optional("priority", parse_json_priority);
+        let due = fields.required("due", parse_json_date);
+        let days_between_recurrences = fields.required("days_between_recurrences", parse_json);
+
+        Some(RecurrenceForm {
+            name: name?,
+            project,
+            priority: PriorityFormField(priority),
+            due: due?,
+            days_between_recurrences: days_between_recurrences?,
+        })
+    }
+}
+
+impl FromJson for TaskChanges {
+    fn from_json(fields: &mut JsonFields) -> Option<Self> {
+        let date = |value| parse_json_date(value).map(|date| date.0);
+
+        Some(TaskChanges {
+            name: fields.non_null("name", parse_json),
+            project: fields.nullable("project", parse_json_project),
+            priority: fields.nullable("priority", parse_json_priority),
+            due: fields.nullable("due", date),
+            wait: fields.nullable("wait", date),
+        })
+    }
+}
+
+impl FromJson for TaskPatch {
+    fn from_json(fields: &mut JsonFields) -> Option<Self> {
+        let add_tags = fields.optional("add_tags", parse_json_tags);
+        let remove_tags = fields.optional("remove_tags", parse_json_tags);
+
+        Some(TaskPatch {
+            changes: TaskChanges::from_json(fields)?,
+            add_tags: add_tags.unwrap_or_default(),
+            remove_tags: remove_tags.unwrap_or_default(),
+        })
+    }
+}
+
+impl FromJson for RecurrenceChanges {
+    fn from_json(fields: &mut JsonFields) -> Option<Self> {
+        Some(RecurrenceChanges {
+            name: fields.non_null("name", parse_json),
+            project: fields.nullable("project", parse_json_project),
+            priority: fields.nullable("priority", parse_json_priority),
+            due: fields.non_null("due", |value| parse_json_date(value).map(|date| date.0)),
+            days_between_recurrences: fields.non_null("days_between_recurrences", parse_json),
+        })
+    }
+}
+
+/// Reads any JSON value from `data`.
+async fn json_value_from_data<'r>(
+    req: &'r Request<'_>,
+    data: Data<'r>,
+) -> data::Outcome<'r, Value, InvalidBody> {
+    match <Json<Value> as FromData>::from_data(req, data).await {
+        Outcome::Success(Json(value)) => Outcome::Success(value),
+        Outcome::Failure((status, json::Error::Io(e))) => {
+            Outcome::Failure((status, InvalidBody::new(e)))
+        }
+        Outcome::Failure((status, json::Error::Parse(_, e))) => {
+            Outcome::Failure((status, InvalidBody::new(e)))
+        }
+        Outcome::Forward(data) => Outcome::Forward(data),
+    }
+}
+
+/// Reads a JSON object from `data` into `T`.
+async fn from_json_data<'r, T: FromJson>(
+    req: &'r Request<'_>,
+    data: Data<'r>,
+) -> data::Outcome<'r, T, InvalidBody> {
+    let object = match json_value_from_data(req, data).await {
+        Outcome::Success(Value::Object(object)) => object,
+        Outcome::Success(_) => {
+            return Outcome::Failure((
+                Status::UnprocessableEntity,
+                InvalidBody::new("expected a JSON object"),
+            ))
+        }
+        Outcome::Failure(failure) => return Outcome::Failure(failure),
+        Outcome::Forward(data) => return Outcome::Forward(data),
+    };
+
+    let mut fields = JsonFields {
+        object,
+        errors: vec![],
+    };
+    match T::from_json(&mut fields) {
+        Some(value) if fields.errors.is_empty() => Outcome::Success(value),
+        _ => Outcome::Failure((
+            Status::UnprocessableEntity,
+            InvalidBody::with_field_errors(fields.errors),
+        )),
+    }
+}
+
+/// A request body that's read into `T` from a JSON object.
+pub struct JsonObject<T>(pub T);
+
+#[rocket::async_trait]
+impl<'r, T: FromJson + Send + 'r> FromData<'r> for JsonObject<T> {
+    type Error = InvalidBody;
+
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        from_json_data(req, data).await.map(JsonObject)
+    }
+}
+
+/// A request body that's read into `T` from either a form or a JSON object, depending on its
+/// Content-Type. Both are validated by the same rules.
+pub struct FormOrJson<T>(pub T);
+
+#[rocket::async_trait]
+impl<'r, T: FromForm<'r> + FromJson + Send + 'r> FromData<'r> for FormOrJson<T> {
+    type Error = InvalidBody;
+
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        if !matches!(req.content_type(), Some(content_type) if content_type.is_json()) {
+            return match Form::<T>::from_data(req, data).await {
+                Outcome::Success(form) => Outcome::Success(FormOrJson(form.into_inner())),
+                Outcome::Failure((status, errors)) => Outcome::Failure((status, errors.into())),
+                Outcome::Forward(data) => Outcome::Forward(data),
+            };
+        }
+
+        from_json_data(req, data).await.map(FormOrJson)
+    }
+}
+
+/// Reads the task at `index` of a Taskwarrior import, and checks its project and tags by the same
+/// rules as a TaskForm's. Its fields are named like `[3].project`.
+fn check_taskwarrior_task(
+    index: usize,
+    value: Value,
+    errors: &mut Vec<FieldError>,
+) -> Option<TaskwarriorTask> {
+    let task = match serde_json::from_value::<TaskwarriorTask>(value) {
+        Ok(task) => task,
+        Err(e) => {
+            errors.push(FieldError {
+                field: format!("[{}]", index),
+                message: e.to_string(),
+            });
+            return None;
+        }
+    };
+
+    let errors_before = errors.len();
+    if validate_project(&task.project).is_err() {
+        errors.push(FieldError {
+            field: format!("[{}].project", index),
+            message: INVALID_PROJECT.to_string(),
+        });
+    }
+    if validate_tag_names(&task.tags).is_err() {
+        errors.push(FieldError {
+            field: format!("[{}].tags", index),
+            message: INVALID_TAG.to_string(),
+        });
+    }
+
+    Some(task).filter(|_| errors.len() == errors_before)
+}
+
+/// A request body that's a JSON array of tasks in Taskwarrior's format, as `task export` writes
+/// them. Every task is checked, and each bad field is reported.
+pub struct TaskwarriorTasks(pub Vec<TaskwarriorTask>);
+
+#[rocket::async_trait]
+impl<'r> FromData<'r> for TaskwarriorTasks {
+    type Error = InvalidBody;
+
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let values = match json_value_from_data(req, data).await {
+            Outcome::Success(Value::Array(values)) => values,
+            Outcome::Success(_) => {
+                return Outcome::Failure((
+                    Status::UnprocessableEntity,
+                    InvalidBody::new("expected a JSON array of Taskwarrior tasks"),
+                ))
+            }
+            Outcome::Failure(failure) => return Outcome::Failure(failure),
+            Outcome::Forward(data) => return Outcome::Forward(data),
+        };
+
+        let mut errors = vec![];
+        let tasks = values
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, value)| check_taskwarrior_task(index, value, &mut errors))
+            .collect();
+
+        if errors.is_empty() {
+            Outcome::Success(TaskwarriorTasks(tasks))
+        } else {
+            Outcome::Failure((
+                Status::UnprocessableEntity,
+                InvalidBody::with_field_errors(errors),
+            ))
+        }
+    }
+}
+
+/// Reports the tasks that `taskwarrior::prepare_import()` couldn't convert, named like
+/// `TaskwarriorTasks`' fields.
+impl From<Vec<(usize, TaskwarriorError)>> for InvalidBody {
+    fn from(errors: Vec<(usize, TaskwarriorError)>) -> Self {
+        InvalidBody::with_field_errors(
+            errors
+                .into_iter()
+                .map(|(index, error)| FieldError {
+                    field: format!("[{}].{}", index, error.field()),
+                    message: error.to_string(),
+                })
+                .collect(),
+        )
+    }
+}
+
 // Wrapper types to work around the orphan rule.
 pub struct WrappedNewTask(pub NewTask);
 
-impl From<Form<TaskForm>> for WrappedNewTask {
-    fn from(form: Form<TaskForm>) -> Self {
-        let form = form.into_inner();
+impl From<FormOrJson<TaskForm>> for WrappedNewTask {
+    fn from(FormOrJson(form): FormOrJson<TaskForm>) -> Self {
         WrappedNewTask(NewTask {
             name: form.name,
             project: form.project,
-            priority: form.priority,
-            due: form.due.map(|due| due.0),
+            priority: form.priority.0,
+            due: form.due.0,
+            wait: form.wait.0,
         })
     }
 }
 
 pub struct WrappedNewRecurrenceTemplate(pub NewRecurrenceTemplate);
 
-impl From<Form<RecurrenceForm>> for WrappedNewRecurrenceTemplate {
-    fn from(form: Form<RecurrenceForm>) -> Self {
-        let form = form.into_inner();
+impl From<FormOrJson<RecurrenceForm>> for WrappedNewRecurrenceTemplate {
+    fn from(FormOrJson(form): FormOrJson<RecurrenceForm>) -> Self {
         WrappedNewRecurrenceTemplate(NewRecurrenceTemplate {
             name: form.name,
             project: form.project,
-            priority: form.priority,
+            priority: form.priority.0,
             due: form.due.0,
             days_between_recurrences: form.days_between_recurrences,
         })
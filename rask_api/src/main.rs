@@ -1,7 +1,18 @@
+use clap::Clap;
+use rask_api::admin::{self, Opts};
+
 #[rocket::main]
 async fn main() {
-    rask_api::assemble_rocket()
-        .launch()
-        .await
-        .expect("error starting server");
+    match Opts::parse().subcommand {
+        None => rask_api::assemble_rocket()
+            .launch()
+            .await
+            .expect("error starting server"),
+        Some(subcommand) => {
+            if let Err(e) = admin::run(subcommand) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
 }
@@ -3,10 +3,17 @@ use rocket::outcome::Outcome::{Failure, Success};
 use rocket::request::{self, FromRequest};
 use rocket::Request;
 
+use crate::catchers::BadRequestReason;
 use crate::db::DBConn;
 use crate::db_queries;
+use rask_lib::models::User;
+use rask_lib::token::hash_token;
 
-pub struct ApiToken;
+/// The hash of the API token that a request was made with, and the user who owns it.
+pub struct ApiToken {
+    pub token_hash: String,
+    pub user: User,
+}
 
 #[derive(Debug)]
 pub enum ApiTokenError {
@@ -28,31 +35,31 @@ fn parse_auth_header(header: &str) -> Option<&str> {
     Some(split_header[1])
 }
 
-/// Verifies that `req` has an Authorization HTTP header whose value is a known API token.
-async fn validate_request_api_token(req: &Request<'_>) -> Result<(), ApiTokenError> {
+/// Verifies that `req` has an Authorization HTTP header whose value is a known API token
+/// that hasn't expired, and returns the token's hash along with its owner.
+async fn validate_request_api_token(req: &Request<'_>) -> Result<ApiToken, ApiTokenError> {
     let auth_header = req
         .headers()
         .get_one("Authorization")
         .ok_or(ApiTokenError::NoHeader)?;
 
-    let token = parse_auth_header(auth_header)
-        .ok_or(ApiTokenError::MalformedHeader)?
-        .to_string();
+    let token_hash =
+        hash_token(parse_auth_header(auth_header).ok_or(ApiTokenError::MalformedHeader)?);
 
     let db = req
         .guard::<DBConn>()
         .await
         .success_or(ApiTokenError::DatabaseError)?;
 
-    let token_exists = db
-        .run(move |conn| db_queries::token_exists(conn, &token))
+    let hash_to_check = token_hash.clone();
+    let user = db
+        .run(move |conn| db_queries::get_token_user(conn, &hash_to_check))
         .await
         .map_err(|_| ApiTokenError::DatabaseError)?;
 
-    if token_exists {
-        Ok(())
-    } else {
-        Err(ApiTokenError::InvalidToken)
+    match user {
+        Some(user) => Ok(ApiToken { token_hash, user }),
+        None => Err(ApiTokenError::InvalidToken),
     }
 }
 
@@ -62,7 +69,7 @@ impl<'r> FromRequest<'r> for ApiToken {
 
     async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
         match validate_request_api_token(req).await {
-            Ok(()) => Success(ApiToken),
+            Ok(token) => Success(token),
             Err(ApiTokenError::DatabaseError) => {
                 Failure((Status::InternalServerError, ApiTokenError::DatabaseError))
             }
@@ -70,6 +77,11 @@ impl<'r> FromRequest<'r> for ApiToken {
                 Failure((Status::Unauthorized, ApiTokenError::InvalidToken))
             }
             Err(ApiTokenError::MalformedHeader) => {
+                req.local_cache(|| {
+                    BadRequestReason(Some(
+                        "the Authorization header should look like `Bearer TOKEN`",
+                    ))
+                });
                 Failure((Status::BadRequest, ApiTokenError::MalformedHeader))
             }
             Err(ApiTokenError::NoHeader) => {
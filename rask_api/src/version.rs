@@ -0,0 +1,70 @@
+//! Tasks and recurrence templates have a version that goes up whenever they're changed.
+//! GET requests return it as an ETag, and requests that change a task or recurrence have to send
+//! it back in an If-Match header, so that they can't overwrite changes that they haven't seen.
+
+use crate::catchers::BadRequestReason;
+use rocket::http::Status;
+use rocket::outcome::Outcome::{Failure, Success};
+use rocket::request::{self, FromRequest};
+use rocket::response::{self, Responder, Response};
+use rocket::Request;
+
+/// The version that a request expects the task or recurrence it changes to be at.
+pub struct IfMatch(pub i32);
+
+#[derive(Debug)]
+pub enum IfMatchError {
+    NoHeader,
+    MalformedHeader,
+}
+
+/// Formats `version` as an ETag like `"3"`.
+pub fn format_etag(version: i32) -> String {
+    format!("\"{}\"", version)
+}
+
+/// Parses an ETag like `"3"` into the version 3. The quotes may be left out.
+pub fn parse_etag(etag: &str) -> Option<i32> {
+    let etag = etag.trim();
+    etag.strip_prefix('"')
+        .and_then(|etag| etag.strip_suffix('"'))
+        .unwrap_or(etag)
+        .parse()
+        .ok()
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfMatch {
+    type Error = IfMatchError;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        match req.headers().get_one("If-Match") {
+            None => Failure((Status::PreconditionRequired, IfMatchError::NoHeader)),
+            Some(header) => match parse_etag(header) {
+                Some(version) => Success(IfMatch(version)),
+                None => {
+                    req.local_cache(|| {
+                        BadRequestReason(Some(
+                            "the If-Match header should hold a version, like `\"3\"`",
+                        ))
+                    });
+                    Failure((Status::BadRequest, IfMatchError::MalformedHeader))
+                }
+            },
+        }
+    }
+}
+
+/// A response with an ETag header that holds the version of the task or recurrence in its body.
+pub struct WithETag<R> {
+    pub version: i32,
+    pub inner: R,
+}
+
+impl<'r, R: Responder<'r, 'static>> Responder<'r, 'static> for WithETag<R> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        Response::build_from(self.inner.respond_to(request)?)
+            .raw_header("ETag", format_etag(self.version))
+            .ok()
+    }
+}
@@ -1,7 +1,15 @@
-use chrono::{Datelike, NaiveDate, ParseError};
-use clap::Clap;
-use rask_lib::models;
-use thiserror::Error;
+use crate::output::Format;
+use crate::table::Columns;
+use chrono::{Local, NaiveDate};
+use clap::{AppSettings, Clap};
+use once_cell::sync::Lazy;
+use rask_lib::dates::{self, DateExpressionError};
+use rask_lib::filter::{Filter, FilterParseError};
+use rask_lib::models::{self, ModelParseError, Priority, Task};
+use std::fmt;
+use std::iter;
+use std::num::ParseIntError;
+use std::str::FromStr;
 
 #[derive(Debug)]
 pub enum ParseDecision<T> {
@@ -9,19 +17,12 @@ pub enum ParseDecision<T> {
     Delete,
 }
 
-#[derive(Debug, Error)]
-pub enum DateParseError {
-    #[error("Error parsing date")]
-    ChronoError(#[from] ParseError),
-
-    #[error("Date's year was too low: {year:?} (specify MM/DD/YYYY, not MM/DD/YY)")]
-    YearTooLowError { year: i32 },
-}
-
 // Clap seems to treat an Ok(None) value as "this arg was unspecified", so we use
 // an eerily-Option-like ParseDecision enum to represent the situation where the user gave us
 // the string "none".
-fn parse_date_str_or_none_str(date_str: &str) -> Result<ParseDecision<NaiveDate>, DateParseError> {
+fn parse_date_str_or_none_str(
+    date_str: &str,
+) -> Result<ParseDecision<NaiveDate>, DateExpressionError> {
     if date_str == "none" {
         Ok(ParseDecision::Delete)
     } else {
@@ -29,19 +30,123 @@ fn parse_date_str_or_none_str(date_str: &str) -> Result<ParseDecision<NaiveDate>
     }
 }
 
-fn parse_date(date_str: &str) -> Result<NaiveDate, DateParseError> {
-    NaiveDate::parse_from_str(date_str, crate::DATE_FORMAT)
-        .map_err(DateParseError::ChronoError)
-        .and_then(|date| {
-            if date.year() >= 2000 {
-                Ok(date)
-            } else {
-                Err(DateParseError::YearTooLowError { year: date.year() })
-            }
-        })
+/// The values that `--priority` accepts.
+static PRIORITY_VALUES: Lazy<Vec<&'static str>> =
+    Lazy::new(|| Priority::ALL.iter().map(Priority::as_str).collect());
+
+/// The values that `--priority` accepts when modifying, where `none` deletes the priority.
+static PRIORITY_OR_NONE_VALUES: Lazy<Vec<&'static str>> = Lazy::new(|| {
+    PRIORITY_VALUES
+        .iter()
+        .copied()
+        .chain(iter::once("none"))
+        .collect()
+});
+
+fn parse_priority_str_or_none_str(
+    priority_str: &str,
+) -> Result<ParseDecision<Priority>, ModelParseError> {
+    if priority_str == "none" {
+        Ok(ParseDecision::Delete)
+    } else {
+        priority_str.parse().map(ParseDecision::Set)
+    }
+}
+
+/// Parses a date like `05/01/2021`, `2021-05-01`, `tomorrow`, `fri`, `+3d`, or `eom`,
+/// relative to today's local date.
+pub(crate) fn parse_date(date_str: &str) -> Result<NaiveDate, DateExpressionError> {
+    dates::parse_date_expression(
+        date_str,
+        Local::today().naive_local(),
+        &crate::config::get().date_format,
+    )
+}
+
+/// Parses a filter like `project:work` or `due.before:fri`, with dates like `parse_date()`.
+pub(crate) fn parse_filter(filter: &str) -> Result<Filter, FilterParseError> {
+    Filter::parse(
+        filter,
+        Local::today().naive_local(),
+        &crate::config::get().date_format,
+    )
+}
+
+/// How the user referred to a task: by its display ID (`12`), or by its permanent ID (`id:345`).
+/// Completed and deleted tasks don't have display IDs, so they can only be referred to by ID.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TaskRef {
+    DisplayId(i32),
+    Id(i32),
+}
+
+impl FromStr for TaskRef {
+    type Err = ParseIntError;
+
+    fn from_str(task_ref: &str) -> Result<Self, Self::Err> {
+        match task_ref.strip_prefix("id:") {
+            Some(task_id) => task_id.parse().map(TaskRef::Id),
+            None => task_ref.parse().map(TaskRef::DisplayId),
+        }
+    }
+}
+
+impl fmt::Display for TaskRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskRef::DisplayId(display_id) => write!(f, "{}", display_id),
+            TaskRef::Id(task_id) => write!(f, "id:{}", task_id),
+        }
+    }
+}
+
+impl From<&Task> for TaskRef {
+    fn from(task: &Task) -> Self {
+        match task.display_id {
+            Some(display_id) => TaskRef::DisplayId(display_id),
+            None => TaskRef::Id(task.id),
+        }
+    }
+}
+
+/// A change to a task's tags: `+errand` adds the `errand` tag, and `-errand` removes it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TagChange {
+    Add(String),
+    Remove(String),
+}
+
+impl FromStr for TagChange {
+    type Err = String;
+
+    fn from_str(tag_change: &str) -> Result<Self, Self::Err> {
+        let (change, name): (fn(String) -> TagChange, &str) =
+            match (tag_change.strip_prefix('+'), tag_change.strip_prefix('-')) {
+                (Some(name), _) => (TagChange::Add, name),
+                (_, Some(name)) => (TagChange::Remove, name),
+                _ => return Err("Tags must start with + or -".to_string()),
+            };
+
+        // The API enforces these rules too, but checking them here keeps words like `+1` in a
+        // task's name.
+        if !name.starts_with(char::is_alphabetic) {
+            Err("Tags must start with a letter".to_string())
+        } else if name.contains(char::is_whitespace) {
+            Err("Tags must be one word".to_string())
+        } else {
+            Ok(change(name.to_string()))
+        }
+    }
+}
+
+fn parse_added_tag(tag: &str) -> Result<TagChange, String> {
+    match tag.parse()? {
+        TagChange::Add(name) => Ok(TagChange::Add(name)),
+        TagChange::Remove(_) => Err("New tasks can only be given tags with +tag".to_string()),
+    }
 }
 
-fn parse_project(project: &str) -> Result<String, String> {
+pub(crate) fn parse_project(project: &str) -> Result<String, String> {
     if project.split(' ').count() == 1 {
         Ok(project.to_string())
     } else {
@@ -51,35 +156,154 @@ fn parse_project(project: &str) -> Result<String, String> {
 
 #[derive(Clap)]
 pub struct Opts {
+    /// Which `.raskrc` profile to use.
+    #[clap(long, global = true)]
+    pub profile: Option<String>,
+
+    /// How to print tasks and recurrences: for people, or as JSON or TSV for scripts.
+    #[clap(long, global = true, possible_values(&["human", "json", "tsv"]), default_value = "human")]
+    pub format: Format,
+
     #[clap(subcommand)]
     pub subcommand: SubCommand,
 }
 
 #[derive(Clap)]
 pub enum SubCommand {
+    Annotate(AnnotateOpts),
     Complete(CompleteOpts),
     Create(CreateOpts),
+    Delete(DeleteOpts),
+    Denotate(DenotateOpts),
+    Depend(DependOpts),
+    /// Edit a task's name, project, priority, and due date in $EDITOR.
+    Edit(EditOpts),
+    /// Print every task in Taskwarrior's JSON format.
+    Export,
+    Gc,
+    /// Import tasks from Taskwarrior's JSON format, e.g. the output of `task export`.
+    Import(ImportOpts),
     Info(InfoOpts),
     List(ListOpts),
+    /// Show the tasks that were completed in a range of days, grouped by day.
+    Log(LogOpts),
     Modify(ModifyOpts),
+    /// Mark a task active, and start tracking the time spent on it.
+    Start(StartOpts),
+    /// Mark an active task pending again, and stop tracking the time spent on it.
+    Stop(StopOpts),
+    /// Show the total time spent on each project's tasks.
+    Summary,
     Uncomplete(UncompleteOpts),
+    Undelete(UndeleteOpts),
+    Undepend(DependOpts),
+    Undo(UndoOpts),
+    Unwait(UnwaitOpts),
+    Wait(WaitOpts),
     Recur(Recur),
 }
+
+#[derive(Clap)]
+pub struct AnnotateOpts {
+    pub task_id: TaskRef,
+
+    /// The text of the annotation. Multiple words are joined with spaces.
+    #[clap(required = true)]
+    pub text: Vec<String>,
+}
+
 #[derive(Clap)]
 pub struct CompleteOpts {
-    pub task_id: i32,
+    pub task_id: TaskRef,
+}
+
+#[derive(Clap)]
+pub struct DeleteOpts {
+    pub task_id: TaskRef,
+}
+
+#[derive(Clap)]
+pub struct DenotateOpts {
+    pub task_id: TaskRef,
+
+    /// The ID of the annotation to remove, as shown by `rask info`.
+    pub annotation_id: i32,
+}
+
+#[derive(Clap)]
+pub struct DependOpts {
+    pub task_id: TaskRef,
+
+    /// The task that has to be finished before `task_id` can be started.
+    pub depends_on: TaskRef,
+}
+
+#[derive(Clap)]
+pub struct EditOpts {
+    pub task_id: TaskRef,
+}
+
+#[derive(Clap)]
+pub struct ImportOpts {
+    /// The file to read tasks from, or `-` to read them from stdin.
+    pub path: String,
 }
 
 #[derive(Clap)]
 pub struct InfoOpts {
-    pub task_id: i32,
+    pub task_id: TaskRef,
 }
 
+// Lets `-tag` through as a value, while still parsing flags that come after it.
 #[derive(Clap)]
+#[clap(setting = AppSettings::AllowLeadingHyphen)]
 pub struct ListOpts {
+    /// Show completed tasks and waiting tasks too.
     #[clap(long)]
     pub all: bool,
+
+    /// Include deleted tasks. Implies --all.
+    #[clap(long)]
+    pub deleted: bool,
+
+    /// Only show tasks that are waiting.
+    #[clap(long)]
+    pub waiting: bool,
+
+    #[clap(long, possible_values(&["urgency", "id"]), default_value = "urgency")]
+    pub sort: String,
+
+    /// Don't show tasks that are blocked by other unfinished tasks.
+    #[clap(long)]
+    pub hide_blocked: bool,
+
+    /// Show blocked tasks after all of the unblocked ones.
+    #[clap(long, conflicts_with = "hide-blocked")]
+    pub blocked_last: bool,
+
+    /// Which columns to show, separated by commas: any of id, mode, priority, project, due,
+    /// age, urgency, tags, and name. Defaults to the profile's `list_columns`, or to
+    /// id,mode,priority,project,due,age,urgency,name.
+    #[clap(long)]
+    pub columns: Option<Columns>,
+
+    /// Only show tasks that match all of these filters, e.g. `project:work`, `prio:H`,
+    /// `mode:completed`, `due.before:05/01/2021`, `due:fri`, `name:milk`, `+tag`, or `-tag`.
+    /// Without a `mode:` filter, only alive tasks are shown (see --all and --waiting).
+    #[clap(parse(try_from_str = parse_filter))]
+    pub filters: Vec<Filter>,
+}
+#[derive(Clap)]
+pub struct LogOpts {
+    /// The first day to show, e.g. 05/01/2021, yesterday, mon, or -2w. Defaults to a week ago.
+    #[clap(long, allow_hyphen_values = true, parse(try_from_str = parse_date))]
+    pub since: Option<NaiveDate>,
+
+    /// The last day to show. Defaults to today.
+    #[clap(long, allow_hyphen_values = true, parse(try_from_str = parse_date))]
+    pub until: Option<NaiveDate>,
 }
+
 #[derive(Clap, Debug)]
 pub struct CreateOpts {
     pub name: String,
@@ -87,12 +311,20 @@ pub struct CreateOpts {
     #[clap(long, alias = "proj", parse(try_from_str = parse_project))]
     pub project: Option<String>,
 
-    #[clap(long, alias = "prio", possible_values(&["H", "M", "L"]))]
-    pub priority: Option<String>,
+    #[clap(long, alias = "prio", possible_values(&PRIORITY_VALUES))]
+    pub priority: Option<Priority>,
 
-    /// Format: MM/DD/YYYY, e.g. 05/01/2021
+    /// A date like 05/01/2021, tomorrow, fri, +3d, or eom.
     #[clap(short, long, parse(try_from_str = parse_date))]
     pub due: Option<NaiveDate>,
+
+    /// Hide the task until this date, e.g. 05/01/2021, tomorrow, fri, +3d, or eom.
+    #[clap(short, long, parse(try_from_str = parse_date))]
+    pub wait: Option<NaiveDate>,
+
+    /// Tags to give the task, e.g. `+errand`.
+    #[clap(parse(try_from_str = parse_added_tag))]
+    pub tags: Vec<TagChange>,
 }
 
 impl From<CreateOpts> for models::NewTask {
@@ -102,6 +334,8 @@ impl From<CreateOpts> for models::NewTask {
             project,
             priority,
             due,
+            wait,
+            ..
         }: CreateOpts,
     ) -> Self {
         models::NewTask {
@@ -109,33 +343,101 @@ impl From<CreateOpts> for models::NewTask {
             project,
             priority,
             due,
+            wait,
         }
     }
 }
 
 #[derive(Clap, Debug)]
+#[clap(setting = AppSettings::AllowLeadingHyphen)]
 pub struct ModifyOpts {
-    pub task_id: i32,
+    pub task_id: TaskRef,
 
-    /// The task's new name, if you want to change the name.
-    pub name: Option<String>,
+    /// The task's new name, if you want to change the name, and/or tags to add (`+tag`)
+    /// or remove (`-tag`).
+    pub words: Vec<String>,
 
     /// A one-word project name. A value of `none` deletes the project.
     #[clap(long, alias = "proj", parse(try_from_str = parse_project))]
     pub project: Option<String>,
 
     /// A value of `none` deletes the priority.
-    #[clap(long, alias = "prio", possible_values(&["H", "M", "L", "none"]))]
-    pub priority: Option<String>,
-
-    /// Format: MM/DD/YYYY, e.g. 05/01/2021. A value of `none` deletes the due date.
+    #[clap(
+        long,
+        alias = "prio",
+        possible_values(&PRIORITY_OR_NONE_VALUES),
+        parse(try_from_str = parse_priority_str_or_none_str)
+    )]
+    pub priority: Option<ParseDecision<Priority>>,
+
+    /// A date like 05/01/2021, tomorrow, fri, +3d, or eom. A value of `none` deletes the
+    /// due date.
     #[clap(short, long, parse(try_from_str = parse_date_str_or_none_str))]
     pub due: Option<ParseDecision<NaiveDate>>,
 }
 
+impl ModifyOpts {
+    /// Splits `words` into the task's new name, if one was given, and changes to its tags.
+    pub fn name_and_tag_changes(&self) -> (Option<String>, Vec<TagChange>) {
+        let mut name_words = vec![];
+        let mut tag_changes = vec![];
+
+        for word in &self.words {
+            match word.parse() {
+                Ok(tag_change) => tag_changes.push(tag_change),
+                Err(_) => name_words.push(word.as_str()),
+            }
+        }
+
+        let name = if name_words.is_empty() {
+            None
+        } else {
+            Some(name_words.join(" "))
+        };
+
+        (name, tag_changes)
+    }
+}
+
+#[derive(Clap)]
+pub struct StartOpts {
+    pub task_id: TaskRef,
+}
+
+#[derive(Clap)]
+pub struct StopOpts {
+    pub task_id: TaskRef,
+}
+
 #[derive(Clap)]
 pub struct UncompleteOpts {
-    pub task_id: i32,
+    pub task_id: TaskRef,
+}
+
+#[derive(Clap)]
+pub struct UndeleteOpts {
+    pub task_id: TaskRef,
+}
+
+#[derive(Clap)]
+pub struct UndoOpts {
+    /// Don't ask for confirmation before undoing the change.
+    #[clap(short, long)]
+    pub yes: bool,
+}
+
+#[derive(Clap)]
+pub struct UnwaitOpts {
+    pub task_id: TaskRef,
+}
+
+#[derive(Clap)]
+pub struct WaitOpts {
+    pub task_id: TaskRef,
+
+    /// A date like 05/01/2021, tomorrow, fri, +3d, or eom.
+    #[clap(parse(try_from_str = parse_date))]
+    pub until: NaiveDate,
 }
 
 #[derive(Clap)]
@@ -170,10 +472,15 @@ pub struct RecurrenceModifyOpts {
     pub project: Option<String>,
 
     /// A value of `none` deletes the priority.
-    #[clap(long, alias = "prio", possible_values(&["H", "M", "L", "none"]))]
-    pub priority: Option<String>,
-
-    /// Format: MM/DD/YYYY, e.g. 05/01/2021.
+    #[clap(
+        long,
+        alias = "prio",
+        possible_values(&PRIORITY_OR_NONE_VALUES),
+        parse(try_from_str = parse_priority_str_or_none_str)
+    )]
+    pub priority: Option<ParseDecision<Priority>>,
+
+    /// A date like 05/01/2021, tomorrow, fri, +3d, or eom.
     #[clap(short, long, parse(try_from_str = parse_date))]
     pub due: Option<NaiveDate>,
 
@@ -185,7 +492,7 @@ pub struct RecurrenceModifyOpts {
 pub struct RecurrenceCreateOpts {
     pub name: String,
 
-    /// Format: MM/DD/YYYY, e.g. 05/01/2021
+    /// A date like 05/01/2021, tomorrow, fri, +3d, or eom.
     #[clap(short, long, parse(try_from_str = parse_date))]
     pub due: NaiveDate,
 
@@ -194,8 +501,8 @@ pub struct RecurrenceCreateOpts {
     #[clap(long, alias = "proj", parse(try_from_str = parse_project))]
     pub project: Option<String>,
 
-    #[clap(long, alias = "prio", possible_values(&["H", "M", "L"]))]
-    pub priority: Option<String>,
+    #[clap(long, alias = "prio", possible_values(&PRIORITY_VALUES))]
+    pub priority: Option<Priority>,
 }
 
 impl From<RecurrenceCreateOpts> for models::NewRecurrenceTemplate {
@@ -0,0 +1,165 @@
+use crate::args::Opts;
+use crate::table::{Columns, DEFAULT_COLUMNS};
+use crate::DATE_FORMAT;
+use anyhow::{anyhow, Context, Result};
+use clap::{AppSettings, IntoApp};
+use once_cell::sync::{Lazy, OnceCell};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+const DEFAULT_API_ROOT: &str = "https://rask.jrheard.com";
+const DEFAULT_PROFILE: &str = "default";
+
+static CONFIG: OnceCell<Config> = OnceCell::new();
+static DEFAULT_CONFIG: Lazy<Config> = Lazy::new(Config::default);
+
+/// One `[profiles.<name>]` section of a `.raskrc` file. Every setting is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Profile {
+    api_root: Option<String>,
+    api_token: Option<String>,
+    /// Columns for `rask list`, in the same format as its `--columns` flag.
+    list_columns: Option<String>,
+    /// A chrono format string, e.g. `%Y-%m-%d`, used for reading and printing dates.
+    date_format: Option<String>,
+    /// The project that new tasks get if `rask create` isn't given one.
+    default_project: Option<String>,
+}
+
+/// The contents of a `.raskrc` file, e.g.
+///
+/// ```toml
+/// default_profile = "home"
+///
+/// [profiles.home]
+/// api_root = "https://rask.jrheard.com"
+/// api_token = "..."
+///
+/// [profiles.work]
+/// api_root = "http://localhost:8001"
+/// default_project = "work"
+/// list_columns = "id,due,name"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RaskRc {
+    /// The profile that's used when `--profile` isn't given. Defaults to `default`.
+    default_profile: Option<String>,
+    #[serde(default)]
+    profiles: HashMap<String, Profile>,
+}
+
+/// The CLI's settings, from the selected `.raskrc` profile and the environment.
+#[derive(Debug)]
+pub struct Config {
+    pub api_root: String,
+    pub api_token: Option<String>,
+    pub list_columns: Columns,
+    pub date_format: String,
+    pub default_project: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config::from_profile(Profile::default()).expect("default config is valid")
+    }
+}
+
+impl Config {
+    /// `RASK_API_ROOT` and `RASK_API_TOKEN` take precedence over the profile's values.
+    fn from_profile(profile: Profile) -> Result<Self> {
+        let list_columns = profile
+            .list_columns
+            .as_deref()
+            .unwrap_or(DEFAULT_COLUMNS)
+            .parse()
+            .map_err(|e: String| anyhow!(e))
+            .context("Invalid list_columns in .raskrc")?;
+
+        Ok(Config {
+            api_root: env::var("RASK_API_ROOT")
+                .ok()
+                .or(profile.api_root)
+                .unwrap_or_else(|| DEFAULT_API_ROOT.to_string()),
+            api_token: env::var("RASK_API_TOKEN").ok().or(profile.api_token),
+            list_columns,
+            date_format: profile
+                .date_format
+                .unwrap_or_else(|| DATE_FORMAT.to_string()),
+            default_project: profile.default_project,
+        })
+    }
+}
+
+/// `$RASK_CONFIG` if it's set, otherwise `~/.raskrc`.
+fn raskrc_path() -> Option<PathBuf> {
+    env::var_os("RASK_CONFIG")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".raskrc")))
+}
+
+/// Reads the `.raskrc` file and selects `profile_name`, or the file's default profile if
+/// `profile_name` is None. It's fine for the file to be missing, unless a profile was asked for.
+fn load(profile_name: Option<&str>) -> Result<Config> {
+    let path = raskrc_path();
+    let raskrc = match &path {
+        Some(path) => match fs::read_to_string(path) {
+            Ok(contents) => toml::from_str::<RaskRc>(&contents)
+                .with_context(|| format!("Unable to parse {}", path.display()))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => RaskRc::default(),
+            Err(e) => return Err(e).with_context(|| format!("Unable to read {}", path.display())),
+        },
+        None => RaskRc::default(),
+    };
+
+    let explicit = profile_name.is_some();
+    let profile_name = profile_name
+        .map(str::to_string)
+        .or(raskrc.default_profile)
+        .unwrap_or_else(|| DEFAULT_PROFILE.to_string());
+
+    let mut profiles = raskrc.profiles;
+    let profile = match profiles.remove(&profile_name) {
+        Some(profile) => profile,
+        None if !explicit && profile_name == DEFAULT_PROFILE => Profile::default(),
+        None => {
+            return Err(anyhow!(
+                "No profile named {:?} in {}",
+                profile_name,
+                path.map(|path| path.display().to_string())
+                    .unwrap_or_else(|| ".raskrc".to_string())
+            ))
+        }
+    };
+
+    Config::from_profile(profile)
+}
+
+/// Loads the config for the rest of this process to use. Called once, before anything else.
+pub fn init(profile_name: Option<&str>) -> Result<()> {
+    let config = load(profile_name)?;
+    CONFIG
+        .set(config)
+        .map_err(|_| anyhow!("Config was already initialized"))
+}
+
+/// The config loaded by `init()`, or the defaults if `init()` hasn't been called.
+pub fn get() -> &'static Config {
+    CONFIG.get().unwrap_or(&DEFAULT_CONFIG)
+}
+
+/// Finds the value of `--profile` in the command line arguments. This has to happen before
+/// they're fully parsed, because the profile's date format is used while parsing dates, so
+/// errors are ignored here and reported by the full parse.
+pub fn profile_from_args<I: IntoIterator<Item = String>>(args: I) -> Option<String> {
+    Opts::into_app()
+        .setting(AppSettings::IgnoreErrors)
+        .get_matches_from(args)
+        .value_of("profile")
+        .map(str::to_string)
+}
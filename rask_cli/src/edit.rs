@@ -0,0 +1,346 @@
+use crate::args::{parse_date, parse_project, TaskRef};
+use crate::config;
+use anyhow::{anyhow, Context, Result};
+use chrono::NaiveDate;
+use rask_lib::models::{ModelParseError, Priority, Task, TaskChanges};
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use thiserror::Error;
+
+const ERROR_PREFIX: &str = "# Error: ";
+
+/// The fields of a task that `rask edit` lets the user change.
+#[derive(Debug, PartialEq, Eq)]
+pub struct EditedTask {
+    pub name: String,
+    pub project: Option<String>,
+    pub priority: Option<Priority>,
+    pub due: Option<NaiveDate>,
+}
+
+impl From<&Task> for EditedTask {
+    fn from(task: &Task) -> Self {
+        EditedTask {
+            name: task.name.clone(),
+            project: task.project.clone(),
+            priority: task.priority,
+            due: task.due,
+        }
+    }
+}
+
+impl EditedTask {
+    /// The changes that turn `original` into this task. Fields that weren't edited are left out.
+    pub fn changes_from(self, original: &EditedTask) -> TaskChanges {
+        TaskChanges {
+            name: Some(self.name).filter(|name| *name != original.name),
+            project: Some(self.project).filter(|project| *project != original.project),
+            priority: Some(self.priority).filter(|priority| *priority != original.priority),
+            due: Some(self.due).filter(|due| *due != original.due),
+            wait: None,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum EditError {
+    #[error("Line {line}: expected `Field: value`")]
+    MalformedLine { line: usize },
+
+    #[error("Line {line}: unknown field {field:?}; expected Name, Project, Priority, or Due")]
+    UnknownField { line: usize, field: String },
+
+    #[error("Line {line}: {field} was given more than once")]
+    DuplicateField { line: usize, field: &'static str },
+
+    #[error("Name can't be blank")]
+    MissingName,
+
+    #[error("Line {line}: {message}")]
+    InvalidValue { line: usize, message: String },
+}
+
+/// Renders `task` as the text that the user edits. Lines that start with `#` are ignored when
+/// the text is parsed.
+pub fn render(task: &Task) -> String {
+    let date_format = &config::get().date_format;
+
+    format!(
+        "# Editing task {task_ref}. Lines that start with # are ignored.\n\
+         # Leave a value blank to remove it. Priority is one of {priorities}; Due is e.g. {today} \
+         or +3d.\n\
+         # Delete everything to leave the task as it was.\n\
+         \n\
+         Name:     {name}\n\
+         Project:  {project}\n\
+         Priority: {priority}\n\
+         Due:      {due}\n",
+        task_ref = TaskRef::from(task),
+        priorities = Priority::ALL
+            .iter()
+            .map(Priority::as_str)
+            .collect::<Vec<_>>()
+            .join(", "),
+        today = chrono::Local::today().naive_local().format(date_format),
+        name = task.name,
+        project = task.project.as_deref().unwrap_or(""),
+        priority = task.priority.map_or("", |priority| priority.as_str()),
+        due = task
+            .due
+            .map(|due| due.format(date_format).to_string())
+            .unwrap_or_default(),
+    )
+}
+
+/// Parses text in the format that `render()` produces. Returns None if the user deleted
+/// everything, or every problem with the text if it isn't valid.
+pub fn parse(text: &str) -> Result<Option<EditedTask>, Vec<EditError>> {
+    let mut edited_task = EditedTask {
+        name: String::new(),
+        project: None,
+        priority: None,
+        due: None,
+    };
+    let mut seen_fields = HashSet::new();
+    let mut errors = vec![];
+
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field.trim(), value.trim()),
+            None => {
+                errors.push(EditError::MalformedLine { line: line_number });
+                continue;
+            }
+        };
+
+        let field = match field.to_lowercase().as_str() {
+            "name" => "Name",
+            "project" => "Project",
+            "priority" => "Priority",
+            "due" => "Due",
+            _ => {
+                errors.push(EditError::UnknownField {
+                    line: line_number,
+                    field: field.to_string(),
+                });
+                continue;
+            }
+        };
+        if !seen_fields.insert(field) {
+            errors.push(EditError::DuplicateField {
+                line: line_number,
+                field,
+            });
+            continue;
+        }
+
+        let result = match field {
+            "Name" => {
+                edited_task.name = value.to_string();
+                Ok(())
+            }
+            "Project" => {
+                optional(value, parse_project).map(|project| edited_task.project = project)
+            }
+            "Priority" => {
+                optional(value, parse_priority).map(|priority| edited_task.priority = priority)
+            }
+            _ => optional(value, |value| parse_date(value).map_err(|e| e.to_string()))
+                .map(|due| edited_task.due = due),
+        };
+        if let Err(message) = result {
+            errors.push(EditError::InvalidValue {
+                line: line_number,
+                message,
+            });
+        }
+    }
+
+    if seen_fields.is_empty() && errors.is_empty() {
+        return Ok(None);
+    }
+
+    if edited_task.name.is_empty() {
+        errors.push(EditError::MissingName);
+    }
+
+    if errors.is_empty() {
+        Ok(Some(edited_task))
+    } else {
+        Err(errors)
+    }
+}
+
+/// Parses `value` with `parse`, or returns None if it's blank.
+fn optional<T>(
+    value: &str,
+    parse: impl Fn(&str) -> Result<T, String>,
+) -> Result<Option<T>, String> {
+    if value.is_empty() {
+        Ok(None)
+    } else {
+        parse(value).map(Some)
+    }
+}
+
+fn parse_priority(priority: &str) -> Result<Priority, String> {
+    priority.parse().map_err(|e: ModelParseError| e.to_string())
+}
+
+/// Puts `errors` at the top of `edited_text`, replacing any from the last time it was parsed.
+fn add_errors(edited_text: &str, errors: &[EditError]) -> String {
+    errors
+        .iter()
+        .map(|e| format!("{}{}\n", ERROR_PREFIX, e))
+        .chain(
+            edited_text
+                .lines()
+                .filter(|line| !line.starts_with(ERROR_PREFIX))
+                .map(|line| format!("{}\n", line)),
+        )
+        .collect()
+}
+
+/// Runs `$EDITOR` (or `vi`, if it isn't set) on the file at `path`, and waits for it to exit.
+fn launch_editor(path: &Path) -> Result<()> {
+    let editor = env::var("EDITOR").unwrap_or_else(|_| "vi".to_string());
+    let mut words = editor.split_whitespace();
+    let program = words.next().ok_or_else(|| anyhow!("$EDITOR is blank"))?;
+
+    let status = Command::new(program)
+        .args(words)
+        .arg(path)
+        .status()
+        .with_context(|| format!("Unable to run editor {:?}", editor))?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("Editor {:?} exited with {}", editor, status))
+    }
+}
+
+/// Lets the user edit `task` in their editor, re-opening the editor with a list of problems
+/// until the result is valid. Returns None if the user deleted everything.
+pub fn edit(task: &Task) -> Result<Option<EditedTask>> {
+    // The file is removed when it's dropped.
+    let file = tempfile::Builder::new()
+        .prefix("rask-edit-")
+        .suffix(".txt")
+        .tempfile()
+        .context("Unable to create a temporary file")?;
+    let mut text = render(task);
+
+    loop {
+        // Editors often replace the file rather than writing to it, so it's reopened each time.
+        fs::write(file.path(), &text).context("Unable to write task to a temporary file")?;
+        launch_editor(file.path())?;
+        let edited_text = fs::read_to_string(file.path()).context("Unable to read edited task")?;
+
+        match parse(&edited_text) {
+            Ok(edited_task) => return Ok(edited_task),
+            Err(errors) => text = add_errors(&edited_text, &errors),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{add_errors, parse, EditError, EditedTask};
+    use chrono::NaiveDate;
+    use rask_lib::models::Priority;
+
+    #[test]
+    fn test_parse() {
+        let edited_task = parse(
+            "# Editing task 1.\n\
+             \n\
+             Name:     clean litterbox\n\
+             project:  pets\n\
+             Priority: H\n\
+             Due:      2099-05-01\n",
+        );
+        assert_eq!(
+            edited_task.unwrap(),
+            Some(EditedTask {
+                name: "clean litterbox".to_string(),
+                project: Some("pets".to_string()),
+                priority: Some(Priority::High),
+                due: Some(NaiveDate::from_ymd(2099, 5, 1)),
+            })
+        );
+
+        // Blank values remove the field, and missing fields are left blank.
+        let edited_task = parse("Name: clean litterbox\nProject:\n");
+        assert_eq!(
+            edited_task.unwrap(),
+            Some(EditedTask {
+                name: "clean litterbox".to_string(),
+                project: None,
+                priority: None,
+                due: None,
+            })
+        );
+
+        // Deleting everything but the comments cancels the edit.
+        assert_eq!(parse("# Editing task 1.\n\n").unwrap(), None);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let errors = parse(
+            "Name:\n\
+             Projekt: pets\n\
+             Priority: X\n\
+             Priority: H\n\
+             due tomorrow\n",
+        )
+        .unwrap_err();
+
+        assert!(matches!(
+            &errors[..],
+            [
+                EditError::UnknownField { line: 2, field },
+                EditError::InvalidValue { line: 3, .. },
+                EditError::DuplicateField { line: 4, field: "Priority" },
+                EditError::MalformedLine { line: 5 },
+                EditError::MissingName,
+            ] if field == "Projekt"
+        ));
+    }
+
+    #[test]
+    fn test_error_round_trip() {
+        let text = "# Editing task 1.\nName: clean litterbox\nPriority: X\n";
+        let errors = parse(text).unwrap_err();
+        let text = add_errors(text, &errors);
+        assert!(text.starts_with("# Error: Line 3: "));
+
+        // The errors are comments, so fixing the problem is enough for the text to parse.
+        let fixed_text = text.replace("Priority: X", "Priority: L");
+        assert_eq!(
+            parse(&fixed_text).unwrap().unwrap().priority,
+            Some(Priority::Low)
+        );
+
+        // Errors from last time are replaced rather than piling up, even if the line numbers have
+        // moved.
+        let errors = parse(&text).unwrap_err();
+        assert!(matches!(
+            &errors[..],
+            [EditError::InvalidValue { line: 4, .. }]
+        ));
+        let text = add_errors(&text, &errors);
+        assert_eq!(text.matches("# Error: ").count(), 1);
+        assert!(text.starts_with("# Error: Line 4: "));
+    }
+}
@@ -1,23 +1,72 @@
 use crate::args::{
-    CompleteOpts, CreateOpts, InfoOpts, ListOpts, Opts, RecurSubCommand, SubCommand, UncompleteOpts,
+    AnnotateOpts, CompleteOpts, CreateOpts, DeleteOpts, DenotateOpts, DependOpts, InfoOpts,
+    ListOpts, LogOpts, Opts, RecurSubCommand, StartOpts, StopOpts, SubCommand, UncompleteOpts,
+    UndeleteOpts, UndoOpts, UnwaitOpts, WaitOpts,
 };
 use anyhow::{Context, Result};
-use args::{ModifyOpts, RecurrenceCreateOpts, RecurrenceInfoOpts, RecurrenceModifyOpts};
+use args::{
+    EditOpts, ImportOpts, ModifyOpts, RecurrenceCreateOpts, RecurrenceInfoOpts,
+    RecurrenceModifyOpts, TagChange, TaskRef,
+};
+use chrono::{DateTime, Local, NaiveDate, TimeZone, Utc};
 use clap::Clap;
-use rask_lib::models::{NewRecurrenceTemplate, NewTask, RecurrenceTemplate, Task};
+use output::UndoOutcome;
+use rask_lib::filter::{Filter, ModeFilter};
+use rask_lib::models::{
+    ErrorBody, NewRecurrenceTemplate, NewTaggedTask, NewTask, ProjectTime, RecurrenceChanges,
+    RecurrenceTemplate, Task, TaskAnnotation, TaskChanges, TaskHistory, TaskPatch, TaskView,
+};
+use rask_lib::taskwarrior::{self, ImportedRows, TaskwarriorTask};
 use reqwest::blocking::{Client, RequestBuilder, Response};
-use reqwest::header::AUTHORIZATION;
+use reqwest::header::{AUTHORIZATION, IF_MATCH};
+use reqwest::{StatusCode, Url};
+use std::cmp::Ordering;
 use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use thiserror::Error;
 
 pub mod args;
+mod config;
+mod edit;
+mod output;
+mod table;
 
 pub const DATE_FORMAT: &str = "%m/%d/%Y";
 
+/// Formats an optional date for display.
+fn format_optional_date(date: Option<NaiveDate>) -> String {
+    date.map(|date| date.format(&config::get().date_format).to_string())
+        .unwrap_or_else(|| "N/A".to_string())
+}
+
+/// Formats a list of task IDs for display.
+fn format_task_ids(task_ids: &[i32]) -> String {
+    if task_ids.is_empty() {
+        "N/A".to_string()
+    } else {
+        task_ids
+            .iter()
+            .map(|&task_id| TaskRef::Id(task_id).to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Formats a length of time like `1:02:03`, for 1 hour, 2 minutes, and 3 seconds.
+fn format_duration(duration: chrono::Duration) -> String {
+    let seconds = duration.num_seconds().max(0);
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
 /// Turns an `endpoint` like `task/1` into a full API URL.
 fn make_url(endpoint: &str) -> String {
-    let root = env::var("RASK_API_ROOT").unwrap_or_else(|_| "https://rask.jrheard.com".to_string());
-
-    format!("{}/{}", root, endpoint)
+    format!("{}/{}", config::get().api_root, endpoint)
 }
 
 trait Authorizable {
@@ -33,158 +82,632 @@ impl Authorizable for RequestBuilder {
 enum Method {
     Get,
     Post,
+    /// A POST whose body is sent as JSON instead of as a form.
+    PostJson,
+    /// A PATCH, whose body is always sent as JSON.
+    Patch,
+}
+
+#[derive(Debug, Error)]
+enum RequestError {
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+
+    /// The API answered with an error status, and described the error in the response body.
+    #[error("{}", format_error_body(.body))]
+    Api { status: StatusCode, body: ErrorBody },
 }
 
-fn make_request<T>(method: Method, url: String, form: Option<T>) -> reqwest::Result<Response>
+impl RequestError {
+    fn status(&self) -> Option<StatusCode> {
+        match self {
+            RequestError::Http(e) => e.status(),
+            RequestError::Api { status, .. } => Some(*status),
+        }
+    }
+}
+
+/// Formats an error from the API as its message, followed by each bad field on its own line.
+fn format_error_body(body: &ErrorBody) -> String {
+    let mut lines = vec![body.message.clone()];
+    lines.extend(
+        body.errors
+            .iter()
+            .map(|error| format!("  {}: {}", error.field, error.message)),
+    );
+    lines.join("\n")
+}
+
+fn make_request<T>(method: Method, url: String, form: Option<T>) -> Result<Response, RequestError>
+where
+    T: serde::Serialize,
+{
+    make_conditional_request(method, url, None, form)
+}
+
+/// Like `make_request()`, but if `version` is given, the API only makes the request's change if
+/// the task or recurrence that it changes is still at that version, and answers with a 412
+/// otherwise.
+fn make_conditional_request<T>(
+    method: Method,
+    url: String,
+    version: Option<i32>,
+    form: Option<T>,
+) -> Result<Response, RequestError>
 where
     T: serde::Serialize,
 {
-    let token = env::var("RASK_API_TOKEN").expect("No value found for RASK_API_TOKEN");
+    let token = config::get()
+        .api_token
+        .as_deref()
+        .expect("No value found for RASK_API_TOKEN or api_token in .raskrc");
 
     let client = Client::new();
 
     let mut builder = match method {
         Method::Get => client.get(url),
-        Method::Post => client.post(url),
+        Method::Post | Method::PostJson => client.post(url),
+        Method::Patch => client.patch(url),
+    }
+    .add_authorization_header(token);
+
+    if let Some(version) = version {
+        builder = builder.header(IF_MATCH, format!("\"{}\"", version));
     }
-    .add_authorization_header(&token);
 
     if let Some(form) = form {
-        builder = builder.form(&form);
+        builder = match method {
+            Method::PostJson | Method::Patch => builder.json(&form),
+            _ => builder.form(&form),
+        };
+    }
+
+    let response = builder.send()?;
+    let status = response.status();
+    if !status.is_client_error() && !status.is_server_error() {
+        return Ok(response);
     }
 
-    builder.send()?.error_for_status()
+    // Responses that didn't come from the API itself, e.g. from a proxy, won't have an ErrorBody.
+    match response.json::<ErrorBody>() {
+        Ok(body) => Err(RequestError::Api { status, body }),
+        Err(_) => Err(RequestError::Api {
+            status,
+            body: ErrorBody {
+                code: "error".to_string(),
+                message: status.to_string(),
+                errors: vec![],
+            },
+        }),
+    }
 }
 
-/// None if `provided_value` is `Some("none")`, else `provided_value.or(fallback)`
-fn provided_value_or_delete_or_fallback(
-    provided_value: Option<String>,
-    fallback: Option<String>,
-) -> Option<String> {
-    match provided_value.as_deref() {
-        Some("none") => None,
-        _ => provided_value.or(fallback),
+/// How many times a change is retried when the task or recurrence it changes is changed by
+/// someone else first.
+const MAX_CONFLICT_RETRIES: usize = 3;
+
+/// Makes a change to `current`, a task or recurrence, by calling `attempt` with it. If someone
+/// else changed it in the meantime, the API answers with a 412, and the latest version is fetched
+/// with `refetch` so that `attempt` can reapply the user's change on top of it.
+fn retry_on_conflict<R, T>(
+    mut current: R,
+    mut refetch: impl FnMut() -> Result<R>,
+    mut attempt: impl FnMut(&R) -> Result<T, RequestError>,
+) -> Result<T> {
+    for _ in 0..MAX_CONFLICT_RETRIES {
+        match attempt(&current) {
+            Err(e) if e.status() == Some(StatusCode::PRECONDITION_FAILED) => {
+                current = refetch()?;
+            }
+            result => return Ok(result?),
+        }
     }
+
+    Ok(attempt(&current)?)
+}
+
+/// How to change a field that the user might have set or deleted: None if they didn't mention
+/// it, and Some(None) if they deleted it by giving a value of `none`.
+fn provided_value_or_delete(provided_value: Option<String>) -> Option<Option<String>> {
+    provided_value.map(|value| if value == "none" { None } else { Some(value) })
+}
+
+/// Like `provided_value_or_delete()`, for fields that are parsed into a ParseDecision.
+fn decision_change<T>(decision: Option<args::ParseDecision<T>>) -> Option<Option<T>> {
+    decision.map(|decision| match decision {
+        args::ParseDecision::Set(value) => Some(value),
+        args::ParseDecision::Delete => None,
+    })
 }
 
 // Tasks
 
-fn print_task(task: &Task) {
+/// Prints `task`, along with its annotations if the API included them.
+fn print_task(task_view: &TaskView) {
+    let task = &task_view.task;
     println!("==========");
-    println!("Task {}:", task.id);
+    println!("Task {}:", TaskRef::from(task));
     println!("==========");
+    println!("ID:\t\t{}", task.id);
     println!("Name:\t\t{}", task.name);
     println!("Mode:\t\t{}", task.mode);
     println!("Created:\t{}", task.time_created);
+    if let Some(time_completed) = task.time_completed {
+        println!("Completed:\t{}", time_completed);
+    }
+    println!("Modified:\t{}", task.time_modified);
     println!("Project:\t{}", task.project.as_deref().unwrap_or("N/A"));
-    println!("Priority:\t{}", task.priority.as_deref().unwrap_or("N/A"));
     println!(
-        "Due:\t\t{}",
-        task.due
-            .map(|due| due.format(DATE_FORMAT).to_string())
-            .unwrap_or_else(|| "N/A".to_string())
+        "Priority:\t{}",
+        task.priority.map_or("N/A", |priority| priority.as_str())
     );
+    println!("Due:\t\t{}", format_optional_date(task.due));
+    println!("Wait:\t\t{}", format_optional_date(task.wait_until));
+    if task_view.relations.tags.is_empty() {
+        println!("Tags:\t\tN/A");
+    } else {
+        println!("Tags:\t\t{}", task_view.relations.tags.join(" "));
+    }
+    println!(
+        "Blocked by:\t{}",
+        format_task_ids(&task_view.relations.blocked_by)
+    );
+    println!(
+        "Blocks:\t\t{}",
+        format_task_ids(&task_view.relations.blocks)
+    );
+
+    if let Some(time_log) = &task_view.time_log {
+        let now = Utc::now();
+        let time_spent = time_log
+            .iter()
+            .fold(chrono::Duration::zero(), |total, interval| {
+                total + interval.duration(now)
+            });
+        println!("Time spent:\t{}", format_duration(time_spent));
+    }
+
+    if let Some(annotations) = task_view.annotations.as_deref().filter(|a| !a.is_empty()) {
+        println!("Annotations:");
+        for annotation in annotations {
+            println!(
+                "\t{}\t{}\t{}",
+                annotation.id,
+                annotation.time_created.format(&config::get().date_format),
+                annotation.body
+            );
+        }
+    }
 }
 
-fn get_task(task_id: i32) -> Result<Task> {
+fn get_task(task_ref: TaskRef) -> Result<TaskView> {
+    let endpoint = match task_ref {
+        TaskRef::DisplayId(display_id) => format!("task/display/{}", display_id),
+        TaskRef::Id(task_id) => format!("task/{}", task_id),
+    };
+
     Ok(
-        make_request::<NewTask>(Method::Get, make_url(&format!("task/{}", task_id)), None)
+        make_request::<NewTask>(Method::Get, make_url(&endpoint), None)
             .context("Unable to read task info from API")?
-            .json::<Task>()?,
+            .json::<TaskView>()?,
     )
 }
 
-fn complete_task(task_id: i32) -> Result<()> {
-    let task = make_request::<NewTask>(
-        Method::Post,
-        make_url(&format!("task/{}/complete", task_id)),
-        None,
+/// Returns the permanent ID of the task that `task_ref` refers to.
+fn resolve_task_id(task_ref: TaskRef) -> Result<i32> {
+    match task_ref {
+        TaskRef::Id(task_id) => Ok(task_id),
+        TaskRef::DisplayId(_) => get_task(task_ref).map(|task_view| task_view.task.id),
+    }
+}
+
+/// Returns the task with the permanent ID `task_id`.
+fn refetch_task(task_id: i32) -> Result<Task> {
+    get_task(TaskRef::Id(task_id)).map(|task_view| task_view.task)
+}
+
+fn complete_task(task_ref: TaskRef) -> Result<()> {
+    let current = get_task(task_ref)?.task;
+    let task_id = current.id;
+    let task = retry_on_conflict(
+        current,
+        || refetch_task(task_id),
+        |task| {
+            make_conditional_request::<NewTask>(
+                Method::Post,
+                make_url(&format!("task/{}/complete", task_id)),
+                Some(task.version),
+                None,
+            )
+        },
     )
     .context("Unable to mark task completed")?
-    .json::<Task>()?;
+    .json::<TaskView>()?;
 
-    println!("Completed task.");
-    print_task(&task);
-    Ok(())
+    output::message("Completed task.");
+    output::emit(&task, print_task)
 }
 
-fn uncomplete_task(task_id: i32) -> Result<()> {
+fn uncomplete_task(task_ref: TaskRef) -> Result<()> {
+    let task_id = resolve_task_id(task_ref)?;
     let task = make_request::<NewTask>(
         Method::Post,
         make_url(&format!("task/{}/uncomplete", task_id)),
         None,
     )
     .context("Unable to mark task uncompleted")?
-    .json::<Task>()?;
+    .json::<TaskView>()?;
 
-    println!("Uncompleted task.");
-    print_task(&task);
-    Ok(())
+    output::message("Uncompleted task.");
+    output::emit(&task, print_task)
 }
 
-fn create_task(opts: CreateOpts) -> Result<()> {
-    let created_task =
-        make_request::<NewTask>(Method::Post, make_url("task"), Some(NewTask::from(opts)))
-            .context("Unable to create task")?
-            .json::<Task>()?;
+fn start_task(task_ref: TaskRef) -> Result<()> {
+    let task_id = resolve_task_id(task_ref)?;
+    let task = make_request::<NewTask>(
+        Method::Post,
+        make_url(&format!("task/{}/start", task_id)),
+        None,
+    )
+    .context("Unable to start task")?
+    .json::<TaskView>()?;
 
-    println!("Successfully created task.");
-    print_task(&created_task);
-    Ok(())
+    output::message("Started task.");
+    output::emit(&task, print_task)
 }
 
-fn task_info(task_id: i32) -> Result<()> {
-    let task = get_task(task_id)?;
-    print_task(&task);
-    Ok(())
+fn stop_task(task_ref: TaskRef) -> Result<()> {
+    let task_id = resolve_task_id(task_ref)?;
+    let task = make_request::<NewTask>(
+        Method::Post,
+        make_url(&format!("task/{}/stop", task_id)),
+        None,
+    )
+    .context("Unable to stop task")?
+    .json::<TaskView>()?;
+
+    output::message("Stopped task.");
+    output::emit(&task, print_task)
 }
 
-fn list_tasks(include_all_tasks: bool) -> Result<()> {
-    let endpoint = if include_all_tasks {
-        "tasks/all"
-    } else {
-        "tasks/alive"
+fn delete_task(task_ref: TaskRef) -> Result<()> {
+    let task_id = resolve_task_id(task_ref)?;
+    let task = make_request::<NewTask>(
+        Method::Post,
+        make_url(&format!("task/{}/delete", task_id)),
+        None,
+    )
+    .context("Unable to delete task")?
+    .json::<TaskView>()?;
+
+    output::message("Deleted task.");
+    output::emit(&task, print_task)
+}
+
+fn undelete_task(task_ref: TaskRef) -> Result<()> {
+    let task_id = resolve_task_id(task_ref)?;
+    let task = make_request::<NewTask>(
+        Method::Post,
+        make_url(&format!("task/{}/undelete", task_id)),
+        None,
+    )
+    .context("Unable to undelete task")?
+    .json::<TaskView>()?;
+
+    output::message("Undeleted task.");
+    output::emit(&task, print_task)
+}
+
+/// Splits `tag_changes` into the names of the tags to add and the names of the tags to remove.
+fn split_tag_changes(tag_changes: Vec<TagChange>) -> (Vec<String>, Vec<String>) {
+    let mut add_tags = vec![];
+    let mut remove_tags = vec![];
+    for tag_change in tag_changes {
+        match tag_change {
+            TagChange::Add(name) => add_tags.push(name),
+            TagChange::Remove(name) => remove_tags.push(name),
+        }
+    }
+
+    (add_tags, remove_tags)
+}
+
+fn create_task(mut opts: CreateOpts) -> Result<()> {
+    // New tasks' tag changes are all additions; see `parse_added_tag()`.
+    let (tags, _) = split_tag_changes(std::mem::take(&mut opts.tags));
+    if opts.project.is_none() {
+        opts.project = config::get().default_project.clone();
+    }
+    let new_task = NewTaggedTask {
+        task: NewTask::from(opts),
+        tags,
     };
+    let created_task = make_request(Method::PostJson, make_url("task"), Some(new_task))
+        .context("Unable to create task")?
+        .json::<TaskView>()?;
+
+    output::message("Successfully created task.");
+    output::emit(&created_task, print_task)
+}
 
-    let tasks = make_request::<NewTask>(Method::Get, make_url(endpoint), None)
-        .context("Unable to read alive tasks from API")?
-        .json::<Vec<Task>>()?;
+fn task_info(task_ref: TaskRef) -> Result<()> {
+    let task = get_task(task_ref)?;
+    output::emit(&task, print_task)
+}
 
-    println!("Retrieved {} tasks", tasks.len());
-    println!("======================");
-    for task in tasks {
-        println!("{}\t{}", task.id, task.name);
+fn list_tasks(opts: ListOpts) -> Result<()> {
+    let mut filters = opts.filters;
+    if !filters
+        .iter()
+        .any(|filter| matches!(filter, Filter::Mode(_)))
+    {
+        if opts.waiting {
+            filters.push(Filter::Mode(ModeFilter::Waiting));
+        } else if !opts.all && !opts.deleted {
+            filters.push(Filter::Mode(ModeFilter::Alive));
+        }
     }
 
-    Ok(())
+    let mut params = filters
+        .iter()
+        .map(|filter| ("filter", filter.to_string()))
+        .collect::<Vec<_>>();
+    if opts.deleted {
+        params.push(("include_deleted", "true".to_string()));
+    }
+    // Waiting is relative to the user's date, not the server's.
+    params.push(("today", Local::today().naive_local().to_string()));
+    let url = Url::parse_with_params(&make_url("tasks"), params)?.to_string();
+
+    let mut tasks = make_request::<NewTask>(Method::Get, url, None)
+        .context("Unable to read tasks from API")?
+        .json::<Vec<TaskView>>()?;
+
+    // The API returns tasks in ID order, so we only need to sort if we're sorting by urgency.
+    if opts.sort == "urgency" {
+        tasks.sort_by(|a, b| b.urgency.partial_cmp(&a.urgency).unwrap_or(Ordering::Equal));
+    }
+
+    if opts.hide_blocked {
+        tasks.retain(|task_view| !task_view.is_blocked());
+    } else if opts.blocked_last {
+        // sort_by_key is stable, so this keeps the order within blocked and unblocked tasks.
+        tasks.sort_by_key(|task_view| task_view.is_blocked());
+    }
+
+    let columns = opts.columns.as_ref().unwrap_or(&config::get().list_columns);
+    output::emit_all(&tasks, |tasks| {
+        println!("Retrieved {} tasks", tasks.len());
+        if !tasks.is_empty() {
+            println!();
+            println!("{}", table::render(&columns.0, tasks));
+        }
+    })
+}
+
+/// The moment that `date` starts in the local time zone.
+fn local_midnight(date: NaiveDate) -> DateTime<Local> {
+    Local
+        .from_local_datetime(&date.and_hms(0, 0, 0))
+        .earliest()
+        .unwrap_or_else(|| Local.from_utc_datetime(&date.and_hms(0, 0, 0)))
+}
+
+/// Prints the tasks that were completed between `since` and `until`, grouped by the day that
+/// they were completed on.
+fn log_completed_tasks(opts: LogOpts) -> Result<()> {
+    let today = Local::today().naive_local();
+    let since = opts.since.unwrap_or(today - chrono::Duration::days(6));
+    let until = opts.until.unwrap_or(today);
+
+    let params = [
+        ("since", local_midnight(since).to_rfc3339()),
+        ("until", local_midnight(until.succ()).to_rfc3339()),
+    ];
+    let url = Url::parse_with_params(&make_url("tasks/completed"), params)?.to_string();
+
+    let tasks = make_request::<NewTask>(Method::Get, url, None)
+        .context("Unable to read completed tasks from API")?
+        .json::<Vec<TaskView>>()?;
+
+    output::emit_all(&tasks, |tasks| {
+        println!("Completed {} tasks", tasks.len());
+
+        let mut current_day = None;
+        for task_view in tasks {
+            let task = &task_view.task;
+            let time_completed = match task.time_completed {
+                Some(time_completed) => time_completed.with_timezone(&Local),
+                None => continue,
+            };
+
+            let day = time_completed.date().naive_local();
+            if current_day != Some(day) {
+                println!();
+                println!(
+                    "{} {}",
+                    day.format("%A"),
+                    day.format(&config::get().date_format)
+                );
+                current_day = Some(day);
+            }
+
+            println!(
+                "  {}  {}  {}",
+                time_completed.format("%H:%M"),
+                TaskRef::from(task),
+                task.name
+            );
+        }
+    })
 }
 
 fn modify_task(opts: ModifyOpts) -> Result<()> {
-    let task = get_task(opts.task_id)?;
-
-    let new_task_values = NewTask {
-        name: opts.name.unwrap_or(task.name),
-        project: provided_value_or_delete_or_fallback(opts.project, task.project),
-        priority: provided_value_or_delete_or_fallback(opts.priority, task.priority),
-        due: match opts.due {
-            None => task.due,
-            Some(args::ParseDecision::Set(due)) => Some(due),
-            Some(args::ParseDecision::Delete) => None,
+    let current = get_task(opts.task_id)?.task;
+    let task_id = current.id;
+    let (name, tag_changes) = opts.name_and_tag_changes();
+    let (add_tags, remove_tags) = split_tag_changes(tag_changes);
+
+    // Only the fields that the user gave are sent, so that changes that were made to the others
+    // in the meantime, e.g. from another machine, aren't undone.
+    let patch = TaskPatch {
+        changes: TaskChanges {
+            name,
+            project: provided_value_or_delete(opts.project),
+            priority: decision_change(opts.priority),
+            due: decision_change(opts.due),
+            wait: None,
         },
+        add_tags,
+        remove_tags,
     };
 
-    let updated_task = make_request(
-        Method::Post,
-        make_url(&format!("task/{}/modify", task.id)),
-        Some(new_task_values),
+    let updated_task = retry_on_conflict(
+        current,
+        || refetch_task(task_id),
+        |task| {
+            make_conditional_request(
+                Method::Patch,
+                make_url(&format!("task/{}", task_id)),
+                Some(task.version),
+                Some(&patch),
+            )
+        },
     )
     .context("Unable to modify task")?
-    .json::<Task>()?;
+    .json::<TaskView>()?;
+
+    output::message("Updated task.");
+    output::emit(&updated_task, print_task)
+}
 
-    println!("Updated task.");
-    print_task(&updated_task);
+/// Opens the task in the user's editor, and saves whatever changes they make.
+fn edit_task(task_ref: TaskRef) -> Result<()> {
+    let task = get_task(task_ref)?.task;
 
-    Ok(())
+    // Like `rask modify`, only the fields that the user changed are sent, so that changes that
+    // were made to the others while the editor was open aren't undone.
+    let changes = match edit::edit(&task)? {
+        Some(edited_task) => edited_task.changes_from(&edit::EditedTask::from(&task)),
+        None => TaskChanges::default(),
+    };
+    if changes.is_empty() {
+        output::message("Task not modified.");
+        return Ok(());
+    }
+
+    let task_id = task.id;
+    let updated_task = retry_on_conflict(
+        task,
+        || refetch_task(task_id),
+        |task| {
+            make_conditional_request(
+                Method::Patch,
+                make_url(&format!("task/{}", task_id)),
+                Some(task.version),
+                Some(&changes),
+            )
+        },
+    )
+    .context("Unable to modify task")?
+    .json::<TaskView>()?;
+
+    output::message("Updated task.");
+    output::emit(&updated_task, print_task)
+}
+
+/// Makes `task_ref` depend on `depends_on`, or removes that dependency if `add` is false.
+fn change_dependency(task_ref: TaskRef, depends_on: TaskRef, add: bool) -> Result<()> {
+    let task_id = resolve_task_id(task_ref)?;
+    let depends_on_id = resolve_task_id(depends_on)?;
+    let endpoint = if add { "depend" } else { "undepend" };
+
+    let task = make_request(
+        Method::Post,
+        make_url(&format!("task/{}/{}", task_id, endpoint)),
+        Some([("depends_on", depends_on_id)]),
+    )
+    .context("Unable to change task's dependencies")?
+    .json::<TaskView>()?;
+
+    if add {
+        output::message("Added dependency.");
+    } else {
+        output::message("Removed dependency.");
+    }
+    output::emit(&task, print_task)
+}
+
+fn annotate_task(task_ref: TaskRef, text: Vec<String>) -> Result<()> {
+    let task_id = resolve_task_id(task_ref)?;
+    let annotation = make_request(
+        Method::Post,
+        make_url(&format!("task/{}/annotate", task_id)),
+        Some([("body", text.join(" "))]),
+    )
+    .context("Unable to annotate task")?
+    .json::<TaskAnnotation>()?;
+
+    output::message(&format!("Added annotation {}.", annotation.id));
+    task_info(TaskRef::Id(task_id))
+}
+
+fn remove_annotation(task_ref: TaskRef, annotation_id: i32) -> Result<()> {
+    let task_id = resolve_task_id(task_ref)?;
+    make_request::<NewTask>(
+        Method::Post,
+        make_url(&format!(
+            "task/{}/annotation/{}/delete",
+            task_id, annotation_id
+        )),
+        None,
+    )
+    .context("Unable to remove annotation")?;
+
+    output::message(&format!("Removed annotation {}.", annotation_id));
+    task_info(TaskRef::Id(task_id))
+}
+
+fn gc() -> Result<()> {
+    let tasks = make_request::<NewTask>(Method::Post, make_url("gc"), None)
+        .context("Unable to compact display IDs")?
+        .json::<Vec<TaskView>>()?;
+
+    output::emit_all(&tasks, |tasks| {
+        println!("Compacted display IDs of {} tasks.", tasks.len())
+    })
+}
+
+/// Hides the task until `wait`, or unhides it if `wait` is None.
+fn wait_task(task_ref: TaskRef, wait: Option<NaiveDate>) -> Result<()> {
+    let task = get_task(task_ref)?.task;
+    let task_id = task.id;
+    let changes = TaskChanges {
+        wait: Some(wait),
+        ..TaskChanges::default()
+    };
+
+    let updated_task = retry_on_conflict(
+        task,
+        || refetch_task(task_id),
+        |task| {
+            make_conditional_request(
+                Method::Patch,
+                make_url(&format!("task/{}", task_id)),
+                Some(task.version),
+                Some(&changes),
+            )
+        },
+    )
+    .context("Unable to modify task")?
+    .json::<TaskView>()?;
+
+    if wait.is_some() {
+        output::message("Waited task.");
+    } else {
+        output::message("Unwaited task.");
+    }
+    output::emit(&updated_task, print_task)
 }
 
 // Recurrences
@@ -201,9 +724,14 @@ fn print_recurrence(recurrence: &RecurrenceTemplate) {
     );
     println!(
         "Priority:\t{}",
-        recurrence.priority.as_deref().unwrap_or("N/A")
+        recurrence
+            .priority
+            .map_or("N/A", |priority| priority.as_str())
+    );
+    println!(
+        "Due:\t\t{}",
+        recurrence.due.format(&config::get().date_format)
     );
-    println!("Due:\t\t{}", recurrence.due.format(DATE_FORMAT));
     println!("Days between:\t{}", recurrence.days_between_recurrences);
 }
 
@@ -219,8 +747,7 @@ fn get_recurrence(recurrence_id: i32) -> Result<RecurrenceTemplate> {
 
 fn recurrence_info(recurrence_id: i32) -> Result<()> {
     let recurrence = get_recurrence(recurrence_id)?;
-    print_recurrence(&recurrence);
-    Ok(())
+    output::emit(&recurrence, print_recurrence)
 }
 
 fn create_recurrence(opts: RecurrenceCreateOpts) -> Result<()> {
@@ -232,9 +759,8 @@ fn create_recurrence(opts: RecurrenceCreateOpts) -> Result<()> {
     .context("Unable to create recurrence")?
     .json::<RecurrenceTemplate>()?;
 
-    println!("Successfully created recurrence.");
-    print_recurrence(&recurrence);
-    Ok(())
+    output::message("Successfully created recurrence.");
+    output::emit(&recurrence, print_recurrence)
 }
 
 fn list_recurrences() -> Result<()> {
@@ -242,54 +768,293 @@ fn list_recurrences() -> Result<()> {
         .context("Unable to read recurrence templates from API")?
         .json::<Vec<RecurrenceTemplate>>()?;
 
-    println!("Retrieved {} recurrences", recurrences.len());
-    println!("======================");
-    for recurrence in recurrences {
-        println!("{}\t{}", recurrence.id, recurrence.name);
-    }
-
-    Ok(())
+    output::emit_all(&recurrences, |recurrences| {
+        println!("Retrieved {} recurrences", recurrences.len());
+        println!("======================");
+        for recurrence in recurrences {
+            println!("{}\t{}", recurrence.id, recurrence.name);
+        }
+    })
 }
 
 fn modify_recurrence(opts: RecurrenceModifyOpts) -> Result<()> {
-    let recurrence = get_recurrence(opts.recurrence_id)?;
-
-    let new_recurrence_values = NewRecurrenceTemplate {
-        name: opts.name.unwrap_or(recurrence.name),
-        project: provided_value_or_delete_or_fallback(opts.project, recurrence.project),
-        priority: provided_value_or_delete_or_fallback(opts.priority, recurrence.priority),
-        due: opts.due.unwrap_or(recurrence.due),
-        days_between_recurrences: opts
-            .days_between_recurrences
-            .unwrap_or(recurrence.days_between_recurrences),
+    let changes = RecurrenceChanges {
+        name: opts.name,
+        project: provided_value_or_delete(opts.project),
+        priority: decision_change(opts.priority),
+        due: opts.due,
+        days_between_recurrences: opts.days_between_recurrences,
     };
 
-    let updated_recurrence = make_request(
-        Method::Post,
-        make_url(&format!("recurrence/{}/modify", recurrence.id)),
-        Some(new_recurrence_values),
+    let recurrence_id = opts.recurrence_id;
+    let updated_recurrence = retry_on_conflict(
+        get_recurrence(recurrence_id)?,
+        || get_recurrence(recurrence_id),
+        |recurrence| {
+            make_conditional_request(
+                Method::Patch,
+                make_url(&format!("recurrence/{}", recurrence_id)),
+                Some(recurrence.version),
+                Some(&changes),
+            )
+        },
     )
     .context("Unable to modify recurrence")?
     .json::<RecurrenceTemplate>()?;
 
-    println!("Updated recurrence.");
-    print_recurrence(&updated_recurrence);
+    output::message("Updated recurrence.");
+    output::emit(&updated_recurrence, print_recurrence)
+}
+
+// Time log
+
+/// Prints the total time that's been spent on each project.
+fn summary() -> Result<()> {
+    let project_times = make_request::<NewTask>(Method::Get, make_url("time/projects"), None)
+        .context("Unable to read time log from API")?
+        .json::<Vec<ProjectTime>>()?;
+
+    output::emit_all(&project_times, |project_times| {
+        let project_name = |project_time: &ProjectTime| {
+            project_time
+                .project
+                .clone()
+                .unwrap_or_else(|| "(none)".to_string())
+        };
+        let width = project_times
+            .iter()
+            .map(|project_time| project_name(project_time).len())
+            .fold("Project".len(), usize::max);
+
+        println!("{:width$}  Time", "Project", width = width);
+        for project_time in project_times {
+            println!(
+                "{:width$}  {}",
+                project_name(project_time),
+                format_duration(chrono::Duration::seconds(project_time.seconds)),
+                width = width
+            );
+        }
+    })
+}
+
+// Taskwarrior
+
+/// Prints every task and recurrence template in Taskwarrior's JSON format.
+fn export_tasks() -> Result<()> {
+    let tasks = make_request::<NewTask>(
+        Method::Get,
+        make_url("tasks/all?include_deleted=true"),
+        None,
+    )
+    .context("Unable to read tasks from API")?
+    .json::<Vec<TaskView>>()?;
+    let recurrences = make_request::<NewTask>(Method::Get, make_url("recurrences/all"), None)
+        .context("Unable to read recurrence templates from API")?
+        .json::<Vec<RecurrenceTemplate>>()?;
+
+    let today = Local::today().naive_local();
+    let exported = recurrences
+        .iter()
+        .map(taskwarrior::export_recurrence)
+        .chain(
+            tasks
+                .iter()
+                .map(|task_view| taskwarrior::export_task(task_view, today)),
+        )
+        .collect::<Vec<_>>();
+
+    println!("{}", serde_json::to_string_pretty(&exported)?);
+    Ok(())
+}
+
+/// Reads tasks in Taskwarrior's JSON format from `path`, or from stdin if `path` is `-`.
+/// Accepts both a JSON array, like `task export` prints, and one JSON object per line,
+/// like older versions of Taskwarrior print.
+fn read_taskwarrior_tasks(path: &str) -> Result<Vec<TaskwarriorTask>> {
+    let contents = if path == "-" {
+        let mut contents = String::new();
+        io::stdin().read_to_string(&mut contents)?;
+        contents
+    } else {
+        fs::read_to_string(path).with_context(|| format!("Unable to read {}", path))?
+    };
+
+    if contents.trim_start().starts_with('[') {
+        serde_json::from_str(&contents).context("Unable to parse Taskwarrior tasks")
+    } else {
+        contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).context("Unable to parse Taskwarrior task"))
+            .collect()
+    }
+}
+
+fn import_tasks(path: &str) -> Result<()> {
+    let tasks = read_taskwarrior_tasks(path)?;
+    // Taskwarrior's dates are converted at our offset from UTC, since the API may be in another
+    // timezone.
+    let utc_offset = Local::now().offset().local_minus_utc();
+    let url = Url::parse_with_params(
+        &make_url("tasks/import"),
+        [("utc_offset", utc_offset.to_string())],
+    )?
+    .to_string();
+
+    let imported = make_request(Method::PostJson, url, Some(tasks))
+        .context("Unable to import tasks")?
+        .json::<ImportedRows>()?;
+
+    output::message(&format!(
+        "Imported {} tasks and {} recurrences.",
+        imported.tasks.len(),
+        imported.recurrences.len()
+    ));
+    match output::format() {
+        // Recurrences don't fit in the tasks' TSV table, but JSON can hold both.
+        output::Format::Json => println!("{}", serde_json::to_string_pretty(&imported)?),
+        _ => output::emit_all(&imported.tasks, |_| ())?,
+    }
 
     Ok(())
 }
 
+// History
+
+/// Prints each top-level field whose value differs between `before` and `after`.
+fn print_changed_fields(before: &serde_json::Value, after: &serde_json::Value) {
+    if let (Some(before), Some(after)) = (before.as_object(), after.as_object()) {
+        for (field, after_value) in after {
+            let before_value = before.get(field).unwrap_or(&serde_json::Value::Null);
+
+            if before_value != after_value {
+                println!("{}:\t{} -> {}", field, before_value, after_value);
+            }
+        }
+    }
+}
+
+fn print_change(change: &TaskHistory) {
+    let description = match (change.task_id, change.recurrence_template_id) {
+        (Some(task_id), _) => format!("task {}", TaskRef::Id(task_id)),
+        (None, Some(recurrence_id)) => format!("recurrence {}", recurrence_id),
+        (None, None) => "a task that no longer exists".to_string(),
+    };
+
+    match &change.before {
+        None => println!("Created {} at {}.", description, change.time_created),
+        Some(before) => {
+            println!("Modified {} at {}:", description, change.time_created);
+            print_changed_fields(before, &change.after);
+        }
+    }
+}
+
+/// Asks the user a yes/no question on stdin, and returns true if they answered yes.
+/// The question goes to stderr when stdout is being used for JSON or TSV output.
+fn confirm(question: &str) -> Result<bool> {
+    if output::format() == output::Format::Human {
+        print!("{} [y/N] ", question);
+        io::stdout().flush()?;
+    } else {
+        eprint!("{} [y/N] ", question);
+    }
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn undo(skip_confirmation: bool) -> Result<()> {
+    let change = match make_request::<NewTask>(Method::Get, make_url("history/latest"), None) {
+        Ok(response) => response.json::<TaskHistory>()?,
+        Err(e) if e.status() == Some(StatusCode::NOT_FOUND) => {
+            output::message("Nothing to undo.");
+            return output::emit(
+                &UndoOutcome {
+                    undone: false,
+                    change: None,
+                },
+                |_| (),
+            );
+        }
+        Err(e) => return Err(e).context("Unable to read history from API"),
+    };
+
+    if output::format() == output::Format::Human {
+        println!("The most recent change was:");
+        print_change(&change);
+    }
+
+    if !skip_confirmation
+        && !confirm("Undo this change? Annotations and dependencies won't be restored.")?
+    {
+        output::message("Not undoing.");
+        return output::emit(
+            &UndoOutcome {
+                undone: false,
+                change: Some(change),
+            },
+            |_| (),
+        );
+    }
+
+    // The API refuses if another change was made after the one that the user confirmed.
+    make_request::<NewTask>(Method::Post, make_url(&format!("undo/{}", change.id)), None)
+        .context("Unable to undo change")?;
+
+    output::message("Undid change.");
+    output::emit(
+        &UndoOutcome {
+            undone: true,
+            change: Some(change),
+        },
+        |_| (),
+    )
+}
+
 pub fn run() -> Result<()> {
     dotenv::dotenv().ok();
+    config::init(config::profile_from_args(env::args()).as_deref())?;
 
     let opts = Opts::parse();
+    output::init(opts.format);
 
     match opts.subcommand {
+        SubCommand::Annotate(AnnotateOpts { task_id, text }) => annotate_task(task_id, text),
         SubCommand::Complete(CompleteOpts { task_id }) => complete_task(task_id),
         SubCommand::Create(create_opts) => create_task(create_opts),
+        SubCommand::Delete(DeleteOpts { task_id }) => delete_task(task_id),
+        SubCommand::Denotate(DenotateOpts {
+            task_id,
+            annotation_id,
+        }) => remove_annotation(task_id, annotation_id),
+        SubCommand::Depend(DependOpts {
+            task_id,
+            depends_on,
+        }) => change_dependency(task_id, depends_on, true),
+        SubCommand::Edit(EditOpts { task_id }) => edit_task(task_id),
+        SubCommand::Export => export_tasks(),
+        SubCommand::Gc => gc(),
+        SubCommand::Import(ImportOpts { path }) => import_tasks(&path),
         SubCommand::Info(InfoOpts { task_id }) => task_info(task_id),
-        SubCommand::List(ListOpts { all }) => list_tasks(all),
+        SubCommand::List(list_opts) => list_tasks(list_opts),
+        SubCommand::Log(log_opts) => log_completed_tasks(log_opts),
         SubCommand::Modify(modify_opts) => modify_task(modify_opts),
+        SubCommand::Start(StartOpts { task_id }) => start_task(task_id),
+        SubCommand::Stop(StopOpts { task_id }) => stop_task(task_id),
+        SubCommand::Summary => summary(),
         SubCommand::Uncomplete(UncompleteOpts { task_id }) => uncomplete_task(task_id),
+        SubCommand::Undelete(UndeleteOpts { task_id }) => undelete_task(task_id),
+        SubCommand::Undepend(DependOpts {
+            task_id,
+            depends_on,
+        }) => change_dependency(task_id, depends_on, false),
+        SubCommand::Undo(UndoOpts { yes }) => undo(yes),
+        SubCommand::Unwait(UnwaitOpts { task_id }) => wait_task(task_id, None),
+        SubCommand::Wait(WaitOpts { task_id, until }) => wait_task(task_id, Some(until)),
         SubCommand::Recur(recur) => match recur.subcommand {
             RecurSubCommand::Create(create_opts) => create_recurrence(create_opts),
             RecurSubCommand::Info(RecurrenceInfoOpts { recurrence_id }) => {
@@ -0,0 +1,246 @@
+use anyhow::Result;
+use once_cell::sync::OnceCell;
+use rask_lib::models::{ProjectTime, RecurrenceTemplate, TaskHistory, TaskView};
+use serde::Serialize;
+use std::fmt::Display;
+use std::str::FromStr;
+
+static FORMAT: OnceCell<Format> = OnceCell::new();
+
+/// How the CLI prints the tasks and recurrences that it gets back from the API.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Aligned, labeled output for people to read.
+    Human,
+    /// One JSON value per command: an object for a single task, or an array for `list`.
+    Json,
+    /// A header row followed by one tab-separated row per task or recurrence.
+    Tsv,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "human" => Ok(Format::Human),
+            "json" => Ok(Format::Json),
+            "tsv" => Ok(Format::Tsv),
+            _ => Err(format!("Unknown format {:?}", format)),
+        }
+    }
+}
+
+/// Sets the format for the rest of this process to use.
+pub fn init(format: Format) {
+    FORMAT.set(format).ok();
+}
+
+pub fn format() -> Format {
+    *FORMAT.get().unwrap_or(&Format::Human)
+}
+
+/// Values that can be printed as a row of a TSV table.
+pub trait TsvRow {
+    fn tsv_header() -> &'static [&'static str];
+    fn tsv_fields(&self) -> Vec<String>;
+}
+
+fn optional<T: Display>(value: &Option<T>) -> String {
+    value
+        .as_ref()
+        .map(|value| value.to_string())
+        .unwrap_or_default()
+}
+
+fn joined<T: Display>(values: &[T]) -> String {
+    values
+        .iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl TsvRow for TaskView {
+    fn tsv_header() -> &'static [&'static str] {
+        &[
+            "id",
+            "display_id",
+            "name",
+            "mode",
+            "project",
+            "priority",
+            "due",
+            "wait_until",
+            "time_created",
+            "time_completed",
+            "time_modified",
+            "urgency",
+            "tags",
+            "blocked_by",
+            "blocks",
+        ]
+    }
+
+    fn tsv_fields(&self) -> Vec<String> {
+        let task = &self.task;
+        vec![
+            task.id.to_string(),
+            optional(&task.display_id),
+            task.name.clone(),
+            task.mode.to_string(),
+            optional(&task.project),
+            optional(&task.priority),
+            optional(&task.due),
+            optional(&task.wait_until),
+            task.time_created.to_rfc3339(),
+            optional(&task.time_completed.map(|time| time.to_rfc3339())),
+            task.time_modified.to_rfc3339(),
+            self.urgency.to_string(),
+            joined(&self.relations.tags),
+            joined(&self.relations.blocked_by),
+            joined(&self.relations.blocks),
+        ]
+    }
+}
+
+impl TsvRow for RecurrenceTemplate {
+    fn tsv_header() -> &'static [&'static str] {
+        &[
+            "id",
+            "name",
+            "project",
+            "priority",
+            "due",
+            "days_between_recurrences",
+            "time_created",
+        ]
+    }
+
+    fn tsv_fields(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.name.clone(),
+            optional(&self.project),
+            optional(&self.priority),
+            self.due.to_string(),
+            self.days_between_recurrences.to_string(),
+            self.time_created.to_rfc3339(),
+        ]
+    }
+}
+
+impl TsvRow for TaskHistory {
+    fn tsv_header() -> &'static [&'static str] {
+        &[
+            "id",
+            "task_id",
+            "recurrence_template_id",
+            "time_created",
+            "before",
+            "after",
+        ]
+    }
+
+    fn tsv_fields(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            optional(&self.task_id),
+            optional(&self.recurrence_template_id),
+            self.time_created.to_rfc3339(),
+            optional(&self.before),
+            self.after.to_string(),
+        ]
+    }
+}
+
+/// What `rask undo` did: whether it undid the latest change, and which change that was, if there
+/// was one to undo.
+#[derive(Serialize)]
+pub struct UndoOutcome {
+    pub undone: bool,
+    pub change: Option<TaskHistory>,
+}
+
+impl TsvRow for UndoOutcome {
+    fn tsv_header() -> &'static [&'static str] {
+        &[
+            "undone",
+            "id",
+            "task_id",
+            "recurrence_template_id",
+            "time_created",
+            "before",
+            "after",
+        ]
+    }
+
+    fn tsv_fields(&self) -> Vec<String> {
+        let mut fields = vec![self.undone.to_string()];
+        match &self.change {
+            Some(change) => fields.extend(change.tsv_fields()),
+            None => fields.resize(Self::tsv_header().len(), String::new()),
+        }
+        fields
+    }
+}
+
+impl TsvRow for ProjectTime {
+    fn tsv_header() -> &'static [&'static str] {
+        &["project", "seconds"]
+    }
+
+    fn tsv_fields(&self) -> Vec<String> {
+        vec![optional(&self.project), self.seconds.to_string()]
+    }
+}
+
+/// Escapes backslashes, tabs, and newlines so that `field` stays in its own TSV cell.
+fn escape_tsv(field: &str) -> String {
+    field
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn print_tsv<T: TsvRow>(values: &[T]) {
+    println!("{}", T::tsv_header().join("\t"));
+    for value in values {
+        let fields = value
+            .tsv_fields()
+            .iter()
+            .map(|field| escape_tsv(field))
+            .collect::<Vec<_>>();
+        println!("{}", fields.join("\t"));
+    }
+}
+
+/// Prints a status message like "Completed task.", but only for people.
+pub fn message(text: &str) {
+    if format() == Format::Human {
+        println!("{}", text);
+    }
+}
+
+/// Prints `value` in the current format, using `print_human` for the human format.
+pub fn emit<T: Serialize + TsvRow>(value: &T, print_human: impl FnOnce(&T)) -> Result<()> {
+    match format() {
+        Format::Human => print_human(value),
+        Format::Json => println!("{}", serde_json::to_string_pretty(value)?),
+        Format::Tsv => print_tsv(std::slice::from_ref(value)),
+    }
+
+    Ok(())
+}
+
+/// Prints `values` in the current format, using `print_human` for the human format.
+pub fn emit_all<T: Serialize + TsvRow>(values: &[T], print_human: impl FnOnce(&[T])) -> Result<()> {
+    match format() {
+        Format::Human => print_human(values),
+        Format::Json => println!("{}", serde_json::to_string_pretty(values)?),
+        Format::Tsv => print_tsv(values),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,233 @@
+use crate::args::TaskRef;
+use crate::config;
+use chrono::{DateTime, Utc};
+use rask_lib::models::TaskView;
+use std::env;
+use std::fmt;
+use std::str::FromStr;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Spaces between columns.
+const GUTTER: &str = "  ";
+
+/// Names are never truncated to fewer than this many characters, even if that means the table
+/// is wider than the terminal.
+const MIN_NAME_WIDTH: usize = 10;
+
+/// A column that `rask list` can show.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Column {
+    Id,
+    Mode,
+    Priority,
+    Project,
+    Due,
+    Age,
+    Urgency,
+    Tags,
+    Name,
+}
+
+pub const DEFAULT_COLUMNS: &str = "id,mode,priority,project,due,age,urgency,name";
+
+/// A comma-separated list of columns, e.g. `id,due,name`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Columns(pub Vec<Column>);
+
+impl FromStr for Columns {
+    type Err = String;
+
+    fn from_str(columns: &str) -> Result<Self, Self::Err> {
+        columns
+            .split(',')
+            .map(|column| column.trim().parse())
+            .collect::<Result<_, _>>()
+            .map(Columns)
+    }
+}
+
+impl FromStr for Column {
+    type Err = String;
+
+    fn from_str(column: &str) -> Result<Self, Self::Err> {
+        match column {
+            "id" => Ok(Column::Id),
+            "mode" => Ok(Column::Mode),
+            "priority" | "prio" => Ok(Column::Priority),
+            "project" | "proj" => Ok(Column::Project),
+            "due" => Ok(Column::Due),
+            "age" => Ok(Column::Age),
+            "urgency" => Ok(Column::Urgency),
+            "tags" => Ok(Column::Tags),
+            "name" => Ok(Column::Name),
+            _ => Err(format!(
+                "Unknown column {:?}; expected some of {}, tags",
+                column, DEFAULT_COLUMNS
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Column {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let header = match self {
+            Column::Id => "ID",
+            Column::Mode => "Mode",
+            Column::Priority => "Pri",
+            Column::Project => "Project",
+            Column::Due => "Due",
+            Column::Age => "Age",
+            Column::Urgency => "Urg",
+            Column::Tags => "Tags",
+            Column::Name => "Name",
+        };
+        write!(f, "{}", header)
+    }
+}
+
+/// Formats how long ago `time` was, e.g. `5min`, `3h`, `2d`, `6w`, `4mo`, or `1y`.
+fn format_age(time: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let age = now.signed_duration_since(time);
+
+    if age.num_hours() < 1 {
+        format!("{}min", age.num_minutes().max(0))
+    } else if age.num_days() < 1 {
+        format!("{}h", age.num_hours())
+    } else if age.num_days() < 14 {
+        format!("{}d", age.num_days())
+    } else if age.num_days() < 90 {
+        format!("{}w", age.num_weeks())
+    } else if age.num_days() < 365 {
+        format!("{}mo", age.num_days() / 30)
+    } else {
+        format!("{}y", age.num_days() / 365)
+    }
+}
+
+fn cell(column: Column, task_view: &TaskView, now: DateTime<Utc>) -> String {
+    let task = &task_view.task;
+
+    match column {
+        Column::Id => TaskRef::from(task).to_string(),
+        Column::Mode => task.mode.to_string(),
+        Column::Priority => task
+            .priority
+            .map(|priority| priority.to_string())
+            .unwrap_or_default(),
+        Column::Project => task.project.clone().unwrap_or_default(),
+        Column::Due => task
+            .due
+            .map(|due| due.format(&config::get().date_format).to_string())
+            .unwrap_or_default(),
+        Column::Age => format_age(task.time_created, now),
+        Column::Urgency => format!("{:.1}", task_view.urgency),
+        Column::Tags => task_view.relations.tags.join(" "),
+        Column::Name if task_view.is_blocked() => format!("{} [blocked]", task.name),
+        Column::Name => task.name.clone(),
+    }
+}
+
+/// Shortens `text` to at most `width` columns, marking it with `…` if anything was cut off.
+fn truncate(text: &str, width: usize) -> String {
+    if text.width() <= width {
+        return text.to_string();
+    }
+
+    let mut truncated = String::new();
+    let mut truncated_width = 0;
+    for c in text.chars() {
+        let char_width = c.width().unwrap_or(0);
+        if truncated_width + char_width + 1 > width {
+            break;
+        }
+        truncated.push(c);
+        truncated_width += char_width;
+    }
+    truncated.push('…');
+
+    truncated
+}
+
+/// Pads `text` with spaces until it's `width` columns wide.
+fn pad(text: &str, width: usize) -> String {
+    format!("{}{}", text, " ".repeat(width.saturating_sub(text.width())))
+}
+
+/// The width that the table should fit in: `$COLUMNS` if it's set, otherwise the terminal's
+/// width, or None if stdout isn't a terminal.
+fn available_width() -> Option<usize> {
+    env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .or_else(|| {
+            terminal_size::terminal_size().map(|(terminal_size::Width(width), _)| width as usize)
+        })
+}
+
+/// Renders `tasks` as a column-aligned table with a header row. If the table is wider than the
+/// terminal, the Name column is narrowed and long names are truncated to fit.
+pub fn render(columns: &[Column], tasks: &[TaskView]) -> String {
+    let now = Utc::now();
+    let headers = columns
+        .iter()
+        .map(|column| column.to_string())
+        .collect::<Vec<_>>();
+    let mut rows = tasks
+        .iter()
+        .map(|task_view| {
+            columns
+                .iter()
+                .map(|&column| cell(column, task_view, now))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let mut widths = headers
+        .iter()
+        .map(|header| header.width())
+        .collect::<Vec<_>>();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.width());
+        }
+    }
+
+    let table_width = widths.iter().sum::<usize>() + GUTTER.len() * widths.len().saturating_sub(1);
+    let name_index = columns.iter().position(|&column| column == Column::Name);
+    if let (Some(available), Some(name_index)) = (available_width(), name_index) {
+        if table_width > available {
+            let overflow = table_width - available;
+            let name_width = widths[name_index]
+                .saturating_sub(overflow)
+                .max(MIN_NAME_WIDTH)
+                .min(widths[name_index]);
+            widths[name_index] = name_width;
+            for row in &mut rows {
+                row[name_index] = truncate(&row[name_index], name_width);
+            }
+        }
+    }
+
+    let format_row = |row: &[String]| {
+        row.iter()
+            .zip(&widths)
+            .map(|(cell, &width)| pad(cell, width))
+            .collect::<Vec<_>>()
+            .join(GUTTER)
+            .trim_end()
+            .to_string()
+    };
+
+    let mut lines = vec![
+        format_row(&headers),
+        format_row(
+            &widths
+                .iter()
+                .map(|&width| "-".repeat(width))
+                .collect::<Vec<_>>(),
+        ),
+    ];
+    lines.extend(rows.iter().map(|row| format_row(row)));
+
+    lines.join("\n")
+}
@@ -0,0 +1,130 @@
+use chrono::{Duration, Local, NaiveDate};
+use diesel::prelude::*;
+use diesel::PgConnection;
+use dotenv::dotenv;
+use rask_lib::display_id::next_display_id;
+use rask_lib::history::{record_recurrence_change, record_task_change, TaskSnapshot};
+use rask_lib::models::{NewTask, RecurrenceTemplate, Task};
+use rask_lib::schema::{recurrence_template, task};
+use std::{env, thread, time};
+
+/// How long the daemon sleeps between scans if RASK_DAEMON_INTERVAL_SECONDS isn't set.
+const DEFAULT_INTERVAL_SECONDS: u64 = 60;
+
+/// What the daemon records in `task_history.token_hash` instead of an API token's hash, since it
+/// doesn't use one. Real hashes are hex digits, so this can't match one.
+pub const DAEMON_TOKEN_HASH: &str = "daemon";
+
+/// Creates a Task for `template`'s current recurrence, owned by the template's user, and
+/// advances the template's due date past `today` so that the same recurrence is never created
+/// twice. Both changes are recorded in the history, so that they can be undone.
+///
+/// If the daemon was down for several recurrences, only one Task is created for the missed ones,
+/// and it's due on the most recent of them.
+fn materialize_recurrence(
+    conn: &PgConnection,
+    template: &RecurrenceTemplate,
+    today: NaiveDate,
+) -> QueryResult<Task> {
+    let step = Duration::days(template.days_between_recurrences.max(1).into());
+
+    // Skip ahead to the most recent recurrence that's on or before `today`.
+    let mut due = template.due;
+    while due + step <= today {
+        due += step;
+    }
+
+    let new_task = NewTask {
+        name: template.name.clone(),
+        project: template.project.clone(),
+        priority: template.priority,
+        due: Some(due),
+        wait: None,
+    };
+
+    let task = diesel::insert_into(task::table)
+        .values((
+            new_task,
+            task::recurrence_template_id.eq(template.id),
+            task::display_id.eq(next_display_id(conn, template.user_id)?),
+            task::user_id.eq(template.user_id),
+        ))
+        .get_result(conn)?;
+
+    let advanced_template = diesel::update(recurrence_template::table.find(template.id))
+        .set((
+            recurrence_template::due.eq(due + step),
+            recurrence_template::version.eq(recurrence_template::version + 1),
+        ))
+        .get_result(conn)?;
+
+    record_recurrence_change(conn, DAEMON_TOKEN_HASH, Some(template), &advanced_template)?;
+    record_task_change(
+        conn,
+        DAEMON_TOKEN_HASH,
+        None,
+        &TaskSnapshot::take(conn, &task)?,
+    )?;
+
+    Ok(task)
+}
+
+/// Creates a Task for every RecurrenceTemplate that's due on or before `today`.
+///
+/// Runs in a single transaction, and locks the due templates while it works,
+/// so that a crash or a second daemon can't cause a recurrence to be created twice.
+pub fn materialize_recurrences(conn: &PgConnection, today: NaiveDate) -> QueryResult<Vec<Task>> {
+    conn.transaction(|| {
+        let due_templates = recurrence_template::table
+            .filter(recurrence_template::due.le(today))
+            .order(recurrence_template::id)
+            .for_update()
+            .load::<RecurrenceTemplate>(conn)?;
+
+        due_templates
+            .iter()
+            .map(|template| materialize_recurrence(conn, template, today))
+            .collect()
+    })
+}
+
+/// Scans for due recurrences every RASK_DAEMON_INTERVAL_SECONDS seconds, forever. If the
+/// database can't be reached, or a scan fails, it reconnects before the next scan.
+pub fn run() {
+    dotenv().ok();
+
+    let db_url = env::var("RASK_DATABASE_URL").expect("RASK_DATABASE_URL must be defined");
+    let interval = env::var("RASK_DAEMON_INTERVAL_SECONDS")
+        .ok()
+        .and_then(|seconds| seconds.parse().ok())
+        .unwrap_or(DEFAULT_INTERVAL_SECONDS);
+
+    let mut conn = None;
+
+    loop {
+        if conn.is_none() {
+            conn = PgConnection::establish(&db_url)
+                .map_err(|e| eprintln!("Error connecting to {}: {}", db_url, e))
+                .ok();
+        }
+
+        let result = conn
+            .as_ref()
+            .map(|conn| materialize_recurrences(conn, Local::today().naive_local()));
+        match result {
+            Some(Ok(tasks)) => {
+                for task in tasks {
+                    println!("Created task {} ({})", task.id, task.name);
+                }
+            }
+            Some(Err(e)) => {
+                eprintln!("Error materializing recurrences: {}", e);
+                // The connection may have been lost, so start over with a new one.
+                conn = None;
+            }
+            None => (),
+        }
+
+        thread::sleep(time::Duration::from_secs(interval));
+    }
+}
@@ -0,0 +1,3 @@
+fn main() {
+    rask_daemon::run();
+}
@@ -0,0 +1,9 @@
+CREATE TABLE task_history (
+    id SERIAL PRIMARY KEY,
+    time_created TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    token TEXT NOT NULL,
+    task_id INTEGER REFERENCES task (id) ON DELETE CASCADE,
+    recurrence_template_id INTEGER REFERENCES recurrence_template (id) ON DELETE CASCADE,
+    before JSONB,
+    after JSONB NOT NULL
+);
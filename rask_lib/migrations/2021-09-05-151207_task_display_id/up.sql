@@ -0,0 +1,10 @@
+ALTER TABLE task ADD COLUMN display_id INTEGER UNIQUE;
+
+UPDATE task
+SET display_id = numbered.display_id
+FROM (
+    SELECT id, ROW_NUMBER() OVER (ORDER BY id) AS display_id
+    FROM task
+    WHERE mode IN ('pending', 'active')
+) AS numbered
+WHERE task.id = numbered.id;
@@ -0,0 +1,7 @@
+CREATE TABLE task_annotation (
+    id SERIAL PRIMARY KEY,
+    task_id INTEGER NOT NULL REFERENCES task (id) ON DELETE CASCADE,
+    time_created TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    body TEXT NOT NULL
+);
+CREATE INDEX task_annotation_task_id ON task_annotation (task_id);
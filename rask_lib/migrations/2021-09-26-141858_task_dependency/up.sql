@@ -0,0 +1,8 @@
+-- `task_id` can't be started until `depends_on_id` is completed.
+CREATE TABLE task_dependency (
+    task_id INTEGER NOT NULL REFERENCES task (id) ON DELETE CASCADE,
+    depends_on_id INTEGER NOT NULL REFERENCES task (id) ON DELETE CASCADE,
+    PRIMARY KEY (task_id, depends_on_id),
+    CHECK (task_id <> depends_on_id)
+);
+CREATE INDEX task_dependency_depends_on_id ON task_dependency (depends_on_id);
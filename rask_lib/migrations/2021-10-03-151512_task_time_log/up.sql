@@ -0,0 +1,11 @@
+-- An interval of time that was spent on a task. `time_stopped` is null while the task is active.
+CREATE TABLE task_time_log (
+    id SERIAL PRIMARY KEY,
+    task_id INTEGER NOT NULL REFERENCES task (id) ON DELETE CASCADE,
+    time_started TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    time_stopped TIMESTAMPTZ,
+    CHECK (time_stopped >= time_started)
+);
+CREATE INDEX task_time_log_task_id ON task_time_log (task_id);
+-- A task can only have one interval in progress at a time.
+CREATE UNIQUE INDEX task_time_log_in_progress ON task_time_log (task_id) WHERE time_stopped IS NULL;
@@ -0,0 +1 @@
+ALTER TABLE task DROP COLUMN time_completed, DROP COLUMN time_modified;
@@ -0,0 +1,18 @@
+ALTER TABLE task
+    ADD COLUMN time_completed TIMESTAMPTZ,
+    ADD COLUMN time_modified TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP NOT NULL;
+
+-- Existing tasks were last modified by their latest change in the history table, if there is one.
+UPDATE task SET time_modified = coalesce(
+    (SELECT max(time_created) FROM task_history WHERE task_history.task_id = task.id),
+    task.time_created
+);
+
+-- And completed tasks were completed by the latest change that completed them.
+UPDATE task SET time_completed = (
+    SELECT max(time_created) FROM task_history
+    WHERE task_history.task_id = task.id AND task_history.after->>'mode' = 'completed'
+)
+WHERE mode = 'completed';
+
+CREATE INDEX task_time_completed ON task (time_completed);
@@ -0,0 +1,24 @@
+DROP INDEX task_history_user_id;
+DROP INDEX recurrence_template_user_id;
+
+ALTER TABLE task DROP CONSTRAINT task_user_id_display_id_key;
+
+-- Display IDs were unique across all tasks, so each user's tasks are renumbered after the
+-- previous user's.
+UPDATE task
+SET display_id = numbered.display_id
+FROM (
+    SELECT id, ROW_NUMBER() OVER (ORDER BY user_id, display_id, id) AS display_id
+    FROM task
+    WHERE display_id IS NOT NULL
+) AS numbered
+WHERE task.id = numbered.id;
+
+ALTER TABLE task ADD CONSTRAINT task_display_id_key UNIQUE (display_id);
+
+ALTER TABLE task_history DROP COLUMN user_id;
+ALTER TABLE recurrence_template DROP COLUMN user_id;
+ALTER TABLE task DROP COLUMN user_id;
+ALTER TABLE api_token DROP COLUMN user_id;
+
+DROP TABLE "user";
@@ -0,0 +1,38 @@
+-- Someone who owns tasks and recurrences, and the API tokens that can see and change them.
+CREATE TABLE "user" (
+    id SERIAL PRIMARY KEY,
+    time_created TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    name TEXT NOT NULL UNIQUE
+);
+
+-- Everything that existed before there were users belongs to a single default user.
+INSERT INTO "user" (name)
+SELECT 'default'
+WHERE EXISTS (SELECT 1 FROM api_token)
+    OR EXISTS (SELECT 1 FROM task)
+    OR EXISTS (SELECT 1 FROM recurrence_template);
+
+ALTER TABLE api_token ADD COLUMN user_id INTEGER REFERENCES "user" (id) ON DELETE CASCADE;
+ALTER TABLE task ADD COLUMN user_id INTEGER REFERENCES "user" (id) ON DELETE CASCADE;
+ALTER TABLE recurrence_template ADD COLUMN user_id INTEGER REFERENCES "user" (id) ON DELETE CASCADE;
+ALTER TABLE task_history ADD COLUMN user_id INTEGER REFERENCES "user" (id) ON DELETE CASCADE;
+
+UPDATE api_token SET user_id = (SELECT id FROM "user" WHERE name = 'default');
+UPDATE task SET user_id = (SELECT id FROM "user" WHERE name = 'default');
+UPDATE recurrence_template SET user_id = (SELECT id FROM "user" WHERE name = 'default');
+UPDATE task_history SET user_id = (SELECT id FROM "user" WHERE name = 'default');
+
+-- History rows whose task or recurrence is gone can't be undone anyway.
+DELETE FROM task_history WHERE user_id IS NULL;
+
+ALTER TABLE api_token ALTER COLUMN user_id SET NOT NULL;
+ALTER TABLE task ALTER COLUMN user_id SET NOT NULL;
+ALTER TABLE recurrence_template ALTER COLUMN user_id SET NOT NULL;
+ALTER TABLE task_history ALTER COLUMN user_id SET NOT NULL;
+
+-- Each user's tasks are numbered separately.
+ALTER TABLE task DROP CONSTRAINT task_display_id_key;
+ALTER TABLE task ADD CONSTRAINT task_user_id_display_id_key UNIQUE (user_id, display_id);
+
+CREATE INDEX recurrence_template_user_id ON recurrence_template (user_id);
+CREATE INDEX task_history_user_id ON task_history (user_id);
@@ -0,0 +1,11 @@
+-- Hashes can't be turned back into tokens, so every token will need to be replaced. The hashes
+-- aren't kept, since the old columns would accept them as tokens.
+ALTER TABLE task_history RENAME COLUMN token_hash TO token;
+UPDATE task_history SET token = '';
+
+DROP TABLE api_token;
+
+CREATE TABLE api_token (
+    token TEXT NOT NULL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES "user" (id) ON DELETE CASCADE
+);
@@ -0,0 +1,23 @@
+-- Tokens are stored as SHA-256 hashes, so that reading the database doesn't reveal them.
+CREATE TEMPORARY TABLE old_api_token AS SELECT * FROM api_token;
+DROP TABLE api_token;
+
+CREATE TABLE api_token (
+    id SERIAL PRIMARY KEY,
+    time_created TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    user_id INTEGER NOT NULL REFERENCES "user" (id) ON DELETE CASCADE,
+    -- The hex-encoded SHA-256 hash of the token.
+    token_hash TEXT NOT NULL UNIQUE,
+    -- Which device or person the token was given to, e.g. "laptop".
+    label TEXT,
+    -- The token stops working at this time, if it's set.
+    time_expires TIMESTAMPTZ
+);
+
+INSERT INTO api_token (user_id, token_hash)
+SELECT user_id, encode(sha256(convert_to(token, 'UTF8')), 'hex') FROM old_api_token;
+
+DROP TABLE old_api_token;
+
+UPDATE task_history SET token = encode(sha256(convert_to(token, 'UTF8')), 'hex');
+ALTER TABLE task_history RENAME COLUMN token TO token_hash;
@@ -0,0 +1,2 @@
+ALTER TABLE recurrence_template DROP CONSTRAINT recurrence_template_priority_check;
+ALTER TABLE task DROP CONSTRAINT task_mode_check, DROP CONSTRAINT task_priority_check;
@@ -0,0 +1,11 @@
+-- Blank priorities were never meant to be stored, and mean the same thing as no priority.
+UPDATE task SET priority = NULL WHERE priority = '';
+UPDATE recurrence_template SET priority = NULL WHERE priority = '';
+
+ALTER TABLE task
+    ADD CONSTRAINT task_mode_check
+        CHECK (mode IN ('pending', 'active', 'completed', 'deleted')),
+    ADD CONSTRAINT task_priority_check CHECK (priority IN ('H', 'M', 'L'));
+
+ALTER TABLE recurrence_template
+    ADD CONSTRAINT recurrence_template_priority_check CHECK (priority IN ('H', 'M', 'L'));
@@ -0,0 +1,2 @@
+ALTER TABLE recurrence_template DROP COLUMN version;
+ALTER TABLE task DROP COLUMN version;
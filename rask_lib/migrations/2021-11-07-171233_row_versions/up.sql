@@ -0,0 +1,2 @@
+ALTER TABLE task ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE recurrence_template ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
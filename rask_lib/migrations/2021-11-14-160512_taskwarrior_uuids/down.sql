@@ -0,0 +1 @@
+DROP TABLE taskwarrior_uuid;
@@ -0,0 +1,10 @@
+-- The Taskwarrior UUID that each imported task or recurrence template had, so that importing
+-- the same tasks again skips them.
+CREATE TABLE taskwarrior_uuid (
+    user_id INTEGER NOT NULL REFERENCES "user" (id) ON DELETE CASCADE,
+    uuid TEXT NOT NULL,
+    task_id INTEGER REFERENCES task (id) ON DELETE CASCADE,
+    recurrence_template_id INTEGER REFERENCES recurrence_template (id) ON DELETE CASCADE,
+    PRIMARY KEY (user_id, uuid),
+    CHECK ((task_id IS NULL) <> (recurrence_template_id IS NULL))
+);
@@ -0,0 +1,239 @@
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum DateExpressionError {
+    #[error(
+        "Unrecognized date {expression:?}; expected e.g. {example}, 2021-05-01, today, tomorrow, \
+         fri, +3d, +2w, eow, or eom"
+    )]
+    Unrecognized { expression: String, example: String },
+
+    #[error("Date {0:?} is out of range")]
+    OutOfRange(String),
+
+    #[error(
+        "Date {expression:?} is in the year {year}; expected a year of {} or later",
+        MIN_YEAR
+    )]
+    YearTooEarly { expression: String, year: i32 },
+}
+
+/// Dates before this year are almost certainly typos, like `05/01/202`.
+const MIN_YEAR: i32 = 2000;
+
+/// The last day of the month that `date` is in.
+fn end_of_month(date: NaiveDate) -> NaiveDate {
+    let (year, month) = if date.month() == 12 {
+        (date.year() + 1, 1)
+    } else {
+        (date.year(), date.month() + 1)
+    };
+
+    NaiveDate::from_ymd(year, month, 1).pred()
+}
+
+/// Adds `months` to `date`, moving the day back to the end of the month if the new month is
+/// too short, e.g. January 31st + 1 month = February 28th.
+fn add_months(date: NaiveDate, months: i32) -> Option<NaiveDate> {
+    let month0 = (date.year().checked_mul(12)? + date.month0() as i32).checked_add(months)?;
+    let first_of_month =
+        NaiveDate::from_ymd_opt(month0.div_euclid(12), month0.rem_euclid(12) as u32 + 1, 1)?;
+
+    first_of_month.with_day(date.day().min(end_of_month(first_of_month).day()))
+}
+
+/// Parses an offset from `today` like `+3d`, `+2w`, `+1m`, `+1y`, or `-1d`.
+fn parse_offset(expression: &str, today: NaiveDate) -> Option<Option<NaiveDate>> {
+    let (sign, offset) = match (expression.strip_prefix('+'), expression.strip_prefix('-')) {
+        (Some(offset), _) => (1, offset),
+        (_, Some(offset)) => (-1, offset),
+        _ => return None,
+    };
+    let unit_start = offset.find(|c: char| !c.is_ascii_digit())?;
+    let (count, unit) = offset.split_at(unit_start);
+    let count = count.parse::<i32>().ok()?.checked_mul(sign);
+
+    let date = match unit {
+        "d" => count.and_then(|count| today.checked_add_signed(Duration::days(count.into()))),
+        "w" => count
+            .and_then(|count| count.checked_mul(7))
+            .and_then(|days| today.checked_add_signed(Duration::days(days.into()))),
+        "m" => count.and_then(|count| add_months(today, count)),
+        "y" => count
+            .and_then(|count| count.checked_mul(12))
+            .and_then(|months| add_months(today, months)),
+        _ => return None,
+    };
+
+    Some(date)
+}
+
+/// The first `weekday` after `today`. If today is Friday, `fri` means a week from today.
+fn next_weekday(weekday: Weekday, today: NaiveDate) -> NaiveDate {
+    let days_ahead =
+        (weekday.num_days_from_monday() + 7 - today.weekday().num_days_from_monday()) % 7;
+
+    today
+        + Duration::days(if days_ahead == 0 {
+            7
+        } else {
+            days_ahead.into()
+        })
+}
+
+/// Parses an absolute date in `date_format` or in `YYYY-MM-DD`. Two-digit years are taken to
+/// be in the 2000s, so `05/01/21` is May 1st, 2021.
+fn parse_absolute(expression: &str, date_format: &str) -> Option<NaiveDate> {
+    let date = [date_format, "%Y-%m-%d"]
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(expression, format).ok())?;
+
+    if date.year() < 100 {
+        date.with_year(date.year() + 2000)
+    } else {
+        Some(date)
+    }
+}
+
+/// Parses a date that's either absolute, like `05/01/2021` (in `date_format`) or
+/// `2021-05-01`, or relative to `today`:
+///
+/// * `today`, `tomorrow`, and `yesterday`
+/// * a day of the week like `fri` or `friday`, which means the next one after today
+/// * an offset like `+3d`, `+2w`, `+1m`, or `+1y`
+/// * `eow`, `eom`, or `eoy`: the end (Sunday, last day) of this week, month, or year
+pub fn parse_date_expression(
+    expression: &str,
+    today: NaiveDate,
+    date_format: &str,
+) -> Result<NaiveDate, DateExpressionError> {
+    let normalized = expression.trim().to_lowercase();
+
+    let date = match normalized.as_str() {
+        "today" => Some(Some(today)),
+        "tomorrow" => Some(today.succ_opt()),
+        "yesterday" => Some(today.pred_opt()),
+        "eow" => Some(today.checked_add_signed(Duration::days(
+            (6 - today.weekday().num_days_from_monday()).into(),
+        ))),
+        "eom" => Some(Some(end_of_month(today))),
+        "eoy" => Some(NaiveDate::from_ymd_opt(today.year(), 12, 31)),
+        _ => parse_offset(&normalized, today)
+            .or_else(|| {
+                normalized
+                    .parse::<Weekday>()
+                    .ok()
+                    .map(|weekday| Some(next_weekday(weekday, today)))
+            })
+            .or_else(|| parse_absolute(expression.trim(), date_format).map(Some)),
+    };
+
+    match date {
+        Some(Some(date)) if date.year() < MIN_YEAR => Err(DateExpressionError::YearTooEarly {
+            expression: expression.to_string(),
+            year: date.year(),
+        }),
+        Some(Some(date)) => Ok(date),
+        Some(None) => Err(DateExpressionError::OutOfRange(expression.to_string())),
+        None => Err(DateExpressionError::Unrecognized {
+            expression: expression.to_string(),
+            example: today.format(date_format).to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_date_expression, DateExpressionError};
+    use chrono::NaiveDate;
+
+    /// A Wednesday.
+    fn today() -> NaiveDate {
+        NaiveDate::from_ymd(2021, 9, 15)
+    }
+
+    fn parse(expression: &str) -> Result<NaiveDate, DateExpressionError> {
+        parse_date_expression(expression, today(), "%m/%d/%Y")
+    }
+
+    fn date(year: i32, month: u32, day: u32) -> Result<NaiveDate, DateExpressionError> {
+        Ok(NaiveDate::from_ymd(year, month, day))
+    }
+
+    #[test]
+    fn test_absolute_dates() {
+        assert_eq!(parse("09/01/2021"), date(2021, 9, 1));
+        assert_eq!(parse("2021-09-01"), date(2021, 9, 1));
+        assert_eq!(parse("09/01/21"), date(2021, 9, 1));
+        assert_eq!(
+            parse("09/01/202"),
+            Err(DateExpressionError::YearTooEarly {
+                expression: "09/01/202".to_string(),
+                year: 202,
+            })
+        );
+        assert!(parse("1999-09-01").is_err());
+        assert_eq!(
+            parse_date_expression("01.09.2021", today(), "%d.%m.%Y"),
+            date(2021, 9, 1)
+        );
+    }
+
+    #[test]
+    fn test_named_days() {
+        assert_eq!(parse("today"), date(2021, 9, 15));
+        assert_eq!(parse("tomorrow"), date(2021, 9, 16));
+        assert_eq!(parse("Yesterday"), date(2021, 9, 14));
+    }
+
+    #[test]
+    fn test_weekdays() {
+        assert_eq!(parse("fri"), date(2021, 9, 17));
+        assert_eq!(parse("friday"), date(2021, 9, 17));
+        assert_eq!(parse("mon"), date(2021, 9, 20));
+        // The next Wednesday after a Wednesday is a week away.
+        assert_eq!(parse("wed"), date(2021, 9, 22));
+    }
+
+    #[test]
+    fn test_offsets() {
+        assert_eq!(parse("+3d"), date(2021, 9, 18));
+        assert_eq!(parse("-1d"), date(2021, 9, 14));
+        assert_eq!(parse("+2w"), date(2021, 9, 29));
+        assert_eq!(parse("+1m"), date(2021, 10, 15));
+        assert_eq!(parse("+1y"), date(2022, 9, 15));
+        assert_eq!(
+            parse_date_expression("+1m", NaiveDate::from_ymd(2021, 1, 31), "%m/%d/%Y"),
+            date(2021, 2, 28)
+        );
+        assert_eq!(
+            parse("+99999999d"),
+            Err(DateExpressionError::OutOfRange("+99999999d".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_ends_of_periods() {
+        assert_eq!(parse("eow"), date(2021, 9, 19));
+        assert_eq!(parse("eom"), date(2021, 9, 30));
+        assert_eq!(parse("eoy"), date(2021, 12, 31));
+        assert_eq!(
+            parse_date_expression("eom", NaiveDate::from_ymd(2021, 12, 5), "%m/%d/%Y"),
+            date(2021, 12, 31)
+        );
+    }
+
+    #[test]
+    fn test_unrecognized() {
+        assert_eq!(
+            parse("someday"),
+            Err(DateExpressionError::Unrecognized {
+                expression: "someday".to_string(),
+                example: "09/15/2021".to_string(),
+            })
+        );
+        assert!(parse("+3").is_err());
+        assert!(parse("13/01/2021").is_err());
+    }
+}
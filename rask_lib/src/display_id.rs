@@ -0,0 +1,41 @@
+use crate::schema::task;
+use diesel::prelude::*;
+use diesel::sql_types::Integer;
+
+/// The first key of the advisory locks that serialize display ID assignment, so that they don't
+/// collide with advisory locks that are taken for other reasons.
+const DISPLAY_ID_LOCK: i32 = 1;
+
+/// Locks the display IDs of a user's tasks until the end of the current transaction, so that
+/// concurrent transactions can't give two tasks the same display ID. Has to be called inside a
+/// transaction; taking the lock again in the same transaction is harmless.
+pub fn lock_display_ids(conn: &PgConnection, user_id: i32) -> QueryResult<()> {
+    diesel::sql_query("SELECT pg_advisory_xact_lock($1, $2)")
+        .bind::<Integer, _>(DISPLAY_ID_LOCK)
+        .bind::<Integer, _>(user_id)
+        .execute(conn)
+        .map(|_| ())
+}
+
+/// Returns the lowest display ID that isn't currently in use by any of a user's tasks.
+/// Locks the user's display IDs first; see `lock_display_ids()`.
+pub fn next_display_id(conn: &PgConnection, user_id: i32) -> QueryResult<i32> {
+    lock_display_ids(conn, user_id)?;
+
+    let used_display_ids = task::table
+        .select(task::display_id)
+        .filter(task::user_id.eq(user_id))
+        .filter(task::display_id.is_not_null())
+        .order(task::display_id)
+        .load::<Option<i32>>(conn)?;
+
+    let mut candidate = 1;
+    for display_id in used_display_ids.into_iter().flatten() {
+        if display_id > candidate {
+            break;
+        }
+        candidate = display_id + 1;
+    }
+
+    Ok(candidate)
+}
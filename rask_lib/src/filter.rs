@@ -0,0 +1,321 @@
+use crate::dates::{self, DateExpressionError};
+use crate::models::{Mode, Priority};
+use chrono::{Local, NaiveDate};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Which modes a `mode:` filter matches. `Alive` and `Waiting` match the same tasks as
+/// the `tasks/alive` and `tasks/waiting` endpoints.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModeFilter {
+    Pending,
+    Active,
+    Completed,
+    Deleted,
+    Alive,
+    Waiting,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DateFilter {
+    On(NaiveDate),
+    Before(NaiveDate),
+    After(NaiveDate),
+    Unset,
+}
+
+/// A single term of a task filter, e.g. `project:work` or `+errand`.
+/// A list of filters matches the tasks that match every one of them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Filter {
+    Mode(ModeFilter),
+    /// None matches tasks without a project.
+    Project(Option<String>),
+    /// None matches tasks without a priority.
+    Priority(Option<Priority>),
+    Due(DateFilter),
+    /// Matches tasks whose names contain this text, ignoring case.
+    NameContains(String),
+    HasTag(String),
+    LacksTag(String),
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum FilterParseError {
+    #[error("Unknown filter {0:?}; filters look like `project:work`, `+tag`, or `-tag`")]
+    UnknownFilter(String),
+
+    #[error(
+        "Unknown mode {0:?}; expected one of pending, active, completed, deleted, alive, waiting"
+    )]
+    UnknownMode(String),
+
+    #[error("Unknown priority {0:?}; expected one of H, M, L, or nothing")]
+    UnknownPriority(String),
+
+    #[error(transparent)]
+    InvalidDate(#[from] DateExpressionError),
+
+    #[error("Filter {0:?} needs a value")]
+    MissingValue(String),
+}
+
+/// The format that filters are displayed with, which every date format also accepts.
+const DISPLAY_DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// The date format that `Filter::from_str()` accepts, besides `DISPLAY_DATE_FORMAT`.
+const DEFAULT_DATE_FORMAT: &str = "%m/%d/%Y";
+
+fn parse_mode(mode: &str) -> Result<ModeFilter, FilterParseError> {
+    match mode {
+        "alive" => Ok(ModeFilter::Alive),
+        "waiting" => Ok(ModeFilter::Waiting),
+        _ => match mode.parse() {
+            Ok(Mode::Pending) => Ok(ModeFilter::Pending),
+            Ok(Mode::Active) => Ok(ModeFilter::Active),
+            Ok(Mode::Completed) => Ok(ModeFilter::Completed),
+            Ok(Mode::Deleted) => Ok(ModeFilter::Deleted),
+            Err(_) => Err(FilterParseError::UnknownMode(mode.to_string())),
+        },
+    }
+}
+
+fn parse_priority(priority: &str) -> Result<Option<Priority>, FilterParseError> {
+    if priority.is_empty() {
+        Ok(None)
+    } else {
+        priority
+            .parse()
+            .map(Some)
+            .map_err(|_| FilterParseError::UnknownPriority(priority.to_string()))
+    }
+}
+
+/// Returns `value`, or an error mentioning `filter` if `value` is empty.
+fn required<'a>(filter: &str, value: &'a str) -> Result<&'a str, FilterParseError> {
+    if value.is_empty() {
+        Err(FilterParseError::MissingValue(filter.to_string()))
+    } else {
+        Ok(value)
+    }
+}
+
+impl Filter {
+    /// Parses a filter like `project:work` or `due.before:fri`. Dates can be anything that
+    /// `dates::parse_date_expression()` accepts, so relative dates are relative to `today`, and
+    /// absolute ones are in `date_format` or YYYY-MM-DD.
+    pub fn parse(
+        filter: &str,
+        today: NaiveDate,
+        date_format: &str,
+    ) -> Result<Self, FilterParseError> {
+        let parse_date = |date: &str| {
+            dates::parse_date_expression(date, today, date_format).map_err(FilterParseError::from)
+        };
+
+        if let Some(tag) = filter.strip_prefix('+') {
+            return Ok(Filter::HasTag(required(filter, tag)?.to_string()));
+        }
+        if let Some(tag) = filter.strip_prefix('-') {
+            return Ok(Filter::LacksTag(required(filter, tag)?.to_string()));
+        }
+
+        let (attribute, value) = filter
+            .split_once(':')
+            .ok_or_else(|| FilterParseError::UnknownFilter(filter.to_string()))?;
+
+        match attribute {
+            "mode" => parse_mode(value).map(Filter::Mode),
+            "project" | "proj" => Ok(Filter::Project(if value.is_empty() {
+                None
+            } else {
+                Some(value.to_string())
+            })),
+            "priority" | "prio" => parse_priority(value).map(Filter::Priority),
+            "due" if value.is_empty() => Ok(Filter::Due(DateFilter::Unset)),
+            "due" => parse_date(value).map(|due| Filter::Due(DateFilter::On(due))),
+            "due.before" => {
+                parse_date(required(filter, value)?).map(|due| Filter::Due(DateFilter::Before(due)))
+            }
+            "due.after" => {
+                parse_date(required(filter, value)?).map(|due| Filter::Due(DateFilter::After(due)))
+            }
+            "name" => Ok(Filter::NameContains(required(filter, value)?.to_string())),
+            _ => Err(FilterParseError::UnknownFilter(filter.to_string())),
+        }
+    }
+}
+
+/// Parses a filter with `Filter::parse()`, with relative dates relative to the local date where
+/// this runs. The CLI parses filters itself and sends them with absolute dates, so that they're
+/// relative to the user's date rather than the API's.
+impl FromStr for Filter {
+    type Err = FilterParseError;
+
+    fn from_str(filter: &str) -> Result<Self, Self::Err> {
+        Filter::parse(filter, Local::today().naive_local(), DEFAULT_DATE_FORMAT)
+    }
+}
+
+impl fmt::Display for ModeFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = match self {
+            ModeFilter::Pending => Mode::Pending.as_str(),
+            ModeFilter::Active => Mode::Active.as_str(),
+            ModeFilter::Completed => Mode::Completed.as_str(),
+            ModeFilter::Deleted => Mode::Deleted.as_str(),
+            ModeFilter::Alive => "alive",
+            ModeFilter::Waiting => "waiting",
+        };
+        write!(f, "{}", mode)
+    }
+}
+
+/// Formats filters so that they can be parsed back by `Filter::from_str()`.
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let date = |date: &NaiveDate| date.format(DISPLAY_DATE_FORMAT).to_string();
+
+        match self {
+            Filter::Mode(mode) => write!(f, "mode:{}", mode),
+            Filter::Project(project) => write!(f, "project:{}", project.as_deref().unwrap_or("")),
+            Filter::Priority(priority) => {
+                write!(
+                    f,
+                    "priority:{}",
+                    priority.map_or("", |priority| priority.as_str())
+                )
+            }
+            Filter::Due(DateFilter::On(due)) => write!(f, "due:{}", date(due)),
+            Filter::Due(DateFilter::Before(due)) => write!(f, "due.before:{}", date(due)),
+            Filter::Due(DateFilter::After(due)) => write!(f, "due.after:{}", date(due)),
+            Filter::Due(DateFilter::Unset) => write!(f, "due:"),
+            Filter::NameContains(text) => write!(f, "name:{}", text),
+            Filter::HasTag(tag) => write!(f, "+{}", tag),
+            Filter::LacksTag(tag) => write!(f, "-{}", tag),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DateFilter, Filter, FilterParseError, ModeFilter};
+    use crate::dates::DateExpressionError;
+    use crate::models::Priority;
+    use chrono::NaiveDate;
+
+    /// Parses `filter` as if today were Wednesday, September 15th, 2021.
+    fn parse(filter: &str) -> Result<Filter, FilterParseError> {
+        Filter::parse(filter, NaiveDate::from_ymd(2021, 9, 15), "%m/%d/%Y")
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("project:work"),
+            Ok(Filter::Project(Some("work".to_string())))
+        );
+        assert_eq!(parse("proj:"), Ok(Filter::Project(None)));
+        assert_eq!(parse("prio:H"), Ok(Filter::Priority(Some(Priority::High))));
+        assert_eq!(parse("mode:pending"), Ok(Filter::Mode(ModeFilter::Pending)));
+        assert_eq!(parse("mode:alive"), Ok(Filter::Mode(ModeFilter::Alive)));
+        assert_eq!(
+            parse("due.before:2021-09-01"),
+            Ok(Filter::Due(DateFilter::Before(NaiveDate::from_ymd(
+                2021, 9, 1
+            ))))
+        );
+        assert_eq!(
+            parse("due.after:09/01/2021"),
+            Ok(Filter::Due(DateFilter::After(NaiveDate::from_ymd(
+                2021, 9, 1
+            ))))
+        );
+        assert_eq!(parse("due:"), Ok(Filter::Due(DateFilter::Unset)));
+        assert_eq!(
+            parse("due.before:tomorrow"),
+            Ok(Filter::Due(DateFilter::Before(NaiveDate::from_ymd(
+                2021, 9, 16
+            ))))
+        );
+        assert_eq!(
+            parse("due:fri"),
+            Ok(Filter::Due(DateFilter::On(NaiveDate::from_ymd(
+                2021, 9, 17
+            ))))
+        );
+        assert_eq!(
+            parse("name:litter box"),
+            Ok(Filter::NameContains("litter box".to_string()))
+        );
+        assert_eq!(parse("+errand"), Ok(Filter::HasTag("errand".to_string())));
+        assert_eq!(parse("-errand"), Ok(Filter::LacksTag("errand".to_string())));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse("frobnicate"),
+            Err(FilterParseError::UnknownFilter("frobnicate".to_string()))
+        );
+        assert_eq!(
+            parse("color:red"),
+            Err(FilterParseError::UnknownFilter("color:red".to_string()))
+        );
+        assert_eq!(
+            parse("mode:asleep"),
+            Err(FilterParseError::UnknownMode("asleep".to_string()))
+        );
+        assert_eq!(
+            parse("prio:Z"),
+            Err(FilterParseError::UnknownPriority("Z".to_string()))
+        );
+        assert_eq!(
+            parse("due.before:someday"),
+            Err(FilterParseError::InvalidDate(
+                DateExpressionError::Unrecognized {
+                    expression: "someday".to_string(),
+                    example: "09/15/2021".to_string(),
+                }
+            ))
+        );
+        assert_eq!(
+            parse("due.after:05/01/202"),
+            Err(FilterParseError::InvalidDate(
+                DateExpressionError::YearTooEarly {
+                    expression: "05/01/202".to_string(),
+                    year: 202,
+                }
+            ))
+        );
+        assert_eq!(
+            parse("due.before:"),
+            Err(FilterParseError::MissingValue("due.before:".to_string()))
+        );
+        assert_eq!(
+            parse("+"),
+            Err(FilterParseError::MissingValue("+".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        for filter in [
+            "mode:waiting",
+            "project:work",
+            "project:",
+            "priority:L",
+            "priority:",
+            "due:2021-09-01",
+            "due.before:2021-09-01",
+            "due.after:2021-09-01",
+            "due:",
+            "name:litter box",
+            "+errand",
+            "-errand",
+        ] {
+            assert_eq!(parse(filter).unwrap().to_string(), filter);
+        }
+    }
+}
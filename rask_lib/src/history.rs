@@ -0,0 +1,96 @@
+//! Recording changes to tasks and recurrence templates in the `task_history` table, so that they
+//! can be undone. Shared by the API and the daemon, so that every change is recorded the same way.
+
+use crate::models::{NewTaskHistory, RecurrenceTemplate, Task};
+use crate::schema::{tag, task_history, task_tag};
+use diesel::prelude::*;
+use diesel::result::Error;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// A task as it's stored in the `task_history` table: its row, along with its tags' names.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct TaskSnapshot {
+    #[serde(flatten)]
+    pub task: Task,
+    /// None in snapshots from before tags were recorded, whose tags can't be restored.
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+}
+
+impl TaskSnapshot {
+    /// Snapshots `task` along with the tags that it has now.
+    pub fn take(conn: &PgConnection, task: &Task) -> QueryResult<Self> {
+        let tags = task_tag::table
+            .inner_join(tag::table)
+            .filter(task_tag::task_id.eq(task.id))
+            .select(tag::name)
+            .order(tag::name)
+            .load(conn)?;
+
+        Ok(TaskSnapshot {
+            task: task.clone(),
+            tags: Some(tags),
+        })
+    }
+}
+
+/// Serializes `row` for storage in the `task_history` table.
+fn snapshot<T: Serialize>(row: &T) -> QueryResult<serde_json::Value> {
+    serde_json::to_value(row).map_err(|e| Error::SerializationError(Box::new(e)))
+}
+
+/// Deserializes a row that was stored by `snapshot()`.
+pub fn restore<T: DeserializeOwned>(value: &serde_json::Value) -> QueryResult<T> {
+    serde_json::from_value(value.clone()).map_err(|e| Error::DeserializationError(Box::new(e)))
+}
+
+/// Records that the token with hash `token_hash` changed a task from `before` to `after`, so that
+/// the change can be undone.
+/// `before` should be None if the task was just created.
+pub fn record_task_change(
+    conn: &PgConnection,
+    token_hash: &str,
+    before: Option<&TaskSnapshot>,
+    after: &TaskSnapshot,
+) -> QueryResult<()> {
+    if before == Some(after) {
+        return Ok(());
+    }
+
+    diesel::insert_into(task_history::table)
+        .values(NewTaskHistory {
+            token_hash: token_hash.to_string(),
+            task_id: Some(after.task.id),
+            recurrence_template_id: None,
+            before: before.map(snapshot).transpose()?,
+            after: snapshot(after)?,
+            user_id: after.task.user_id,
+        })
+        .execute(conn)
+        .map(|_| ())
+}
+
+/// Like `record_task_change()`, but for recurrence templates.
+pub fn record_recurrence_change(
+    conn: &PgConnection,
+    token_hash: &str,
+    before: Option<&RecurrenceTemplate>,
+    after: &RecurrenceTemplate,
+) -> QueryResult<()> {
+    if before == Some(after) {
+        return Ok(());
+    }
+
+    diesel::insert_into(task_history::table)
+        .values(NewTaskHistory {
+            token_hash: token_hash.to_string(),
+            task_id: None,
+            recurrence_template_id: Some(after.id),
+            before: before.map(snapshot).transpose()?,
+            after: snapshot(after)?,
+            user_id: after.user_id,
+        })
+        .execute(conn)
+        .map(|_| ())
+}
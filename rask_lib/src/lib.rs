@@ -1,6 +1,13 @@
 #[macro_use]
 extern crate diesel;
 
+pub mod dates;
+pub mod display_id;
+pub mod filter;
+pub mod history;
 pub mod models;
 pub mod schema;
+pub mod taskwarrior;
 pub mod testing;
+pub mod token;
+pub mod urgency;
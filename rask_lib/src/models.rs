@@ -1,40 +1,349 @@
 use crate::schema::api_token;
 use crate::schema::recurrence_template;
+use crate::schema::tag;
 use crate::schema::task;
+use crate::schema::task_annotation;
+use crate::schema::task_dependency;
+use crate::schema::task_history;
+use crate::schema::task_tag;
+use crate::schema::task_time_log;
+use crate::schema::user;
+use crate::urgency::{urgency, UrgencyCoefficients};
 use chrono::Utc;
-use diesel::Queryable;
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::Pg;
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::{BigInt, Nullable, Text};
+use diesel::{Queryable, QueryableByName};
+#[cfg(feature = "rocket")]
+use rocket::form::{self, FromFormField, ValueField};
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use thiserror::Error;
 
-#[derive(Clone, Copy, Debug)]
-pub struct Mode(pub &'static str);
+/// Where a task is in its lifecycle.
+#[derive(
+    AsExpression, FromSqlRow, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash,
+)]
+#[sql_type = "Text"]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    Pending,
+    Active,
+    Completed,
+    Deleted,
+}
+
+impl Mode {
+    pub const ALL: [Mode; 4] = [Mode::Pending, Mode::Active, Mode::Completed, Mode::Deleted];
+
+    /// The modes of tasks that haven't been finished yet.
+    pub const ALIVE: [Mode; 2] = [Mode::Pending, Mode::Active];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Mode::Pending => "pending",
+            Mode::Active => "active",
+            Mode::Completed => "completed",
+            Mode::Deleted => "deleted",
+        }
+    }
+
+    pub fn is_alive(&self) -> bool {
+        Mode::ALIVE.contains(self)
+    }
+}
+
+#[derive(
+    AsExpression, FromSqlRow, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash,
+)]
+#[sql_type = "Text"]
+pub enum Priority {
+    #[serde(rename = "H")]
+    High,
+    #[serde(rename = "M")]
+    Medium,
+    #[serde(rename = "L")]
+    Low,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 3] = [Priority::High, Priority::Medium, Priority::Low];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::High => "H",
+            Priority::Medium => "M",
+            Priority::Low => "L",
+        }
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ModelParseError {
+    #[error("Unknown mode {0:?}; expected one of pending, active, completed, deleted")]
+    UnknownMode(String),
+
+    #[error("Unknown priority {0:?}; expected one of H, M, L")]
+    UnknownPriority(String),
+}
+
+impl FromStr for Mode {
+    type Err = ModelParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Mode::ALL
+            .iter()
+            .copied()
+            .find(|mode| mode.as_str() == s)
+            .ok_or_else(|| ModelParseError::UnknownMode(s.to_string()))
+    }
+}
+
+impl FromStr for Priority {
+    type Err = ModelParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Priority::ALL
+            .iter()
+            .copied()
+            .find(|priority| priority.as_str() == s)
+            .ok_or_else(|| ModelParseError::UnknownPriority(s.to_string()))
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// Both enums are stored as text; the database's CHECK constraints only allow the values above.
+impl ToSql<Text, Pg> for Mode {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        ToSql::<Text, Pg>::to_sql(self.as_str(), out)
+    }
+}
+
+impl FromSql<Text, Pg> for Mode {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        let mode: String = FromSql::<Text, Pg>::from_sql(bytes)?;
+        Ok(mode.parse()?)
+    }
+}
+
+impl ToSql<Text, Pg> for Priority {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        ToSql::<Text, Pg>::to_sql(self.as_str(), out)
+    }
+}
 
-pub const MODE_PENDING: Mode = Mode("pending");
-pub const MODE_ACTIVE: Mode = Mode("active");
-pub const MODE_COMPLETED: Mode = Mode("completed");
-#[allow(dead_code)]
-pub const MODE_DELETED: Mode = Mode("deleted");
+impl FromSql<Text, Pg> for Priority {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        let priority: String = FromSql::<Text, Pg>::from_sql(bytes)?;
+        Ok(priority.parse()?)
+    }
+}
 
-#[derive(Clone, Copy, Debug)]
-pub struct Priority(pub &'static str);
+#[cfg(feature = "rocket")]
+#[rocket::async_trait]
+impl<'v> FromFormField<'v> for Mode {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        field
+            .value
+            .parse()
+            .map_err(|e: ModelParseError| form::Error::validation(e.to_string()).into())
+    }
+}
 
-pub const PRIORITY_HIGH: Priority = Priority("H");
-pub const PRIORITY_MEDIUM: Priority = Priority("M");
-pub const PRIORITY_LOW: Priority = Priority("L");
+#[cfg(feature = "rocket")]
+#[rocket::async_trait]
+impl<'v> FromFormField<'v> for Priority {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        field
+            .value
+            .parse()
+            .map_err(|e: ModelParseError| form::Error::validation(e.to_string()).into())
+    }
+}
 
 #[derive(
-    Queryable, Deserialize, Serialize, Associations, Identifiable, PartialEq, Eq, Debug, Clone,
+    Queryable,
+    Deserialize,
+    Serialize,
+    Associations,
+    Identifiable,
+    AsChangeset,
+    PartialEq,
+    Eq,
+    Debug,
+    Clone,
 )]
 #[belongs_to(RecurrenceTemplate)]
 #[table_name = "task"]
+#[changeset_options(treat_none_as_null = "true")]
 pub struct Task {
     pub id: i32,
     pub name: String,
     pub project: Option<String>,
-    pub priority: Option<String>,
-    pub mode: String,
+    pub priority: Option<Priority>,
+    pub mode: Mode,
     pub time_created: chrono::DateTime<Utc>,
     pub due: Option<chrono::NaiveDate>,
     pub recurrence_template_id: Option<i32>,
+    pub wait_until: Option<chrono::NaiveDate>,
+    /// A small ID that alive tasks are addressed by in the CLI; None for completed and deleted tasks.
+    pub display_id: Option<i32>,
+    /// When the task was completed; None unless it's completed.
+    // Snapshots in the history table from before these fields existed don't have them.
+    #[serde(default)]
+    pub time_completed: Option<chrono::DateTime<Utc>>,
+    /// When the task's fields or mode were last changed.
+    #[serde(default = "Utc::now")]
+    pub time_modified: chrono::DateTime<Utc>,
+    /// The user who owns the task.
+    #[serde(default)]
+    pub user_id: i32,
+    /// Goes up by one whenever the task's fields or mode are changed. The API returns it as an
+    /// ETag, and requests that change the task have to send it back in an If-Match header.
+    #[serde(default)]
+    pub version: i32,
+}
+
+/// The parts of a task that are stored in other tables.
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone, Default)]
+#[serde(default)]
+pub struct TaskRelations {
+    /// The names of the task's tags, in alphabetical order.
+    pub tags: Vec<String>,
+    /// IDs of the unfinished tasks that this task depends on.
+    /// A task is blocked until all of the tasks it depends on are completed or deleted.
+    pub blocked_by: Vec<i32>,
+    /// IDs of the unfinished tasks that depend on this task, if this task is unfinished.
+    pub blocks: Vec<i32>,
+}
+
+/// A Task as returned by the API: its stored fields, plus values derived from them.
+#[derive(Deserialize, Serialize, PartialEq, Debug, Clone)]
+pub struct TaskView {
+    #[serde(flatten)]
+    pub task: Task,
+    pub urgency: f64,
+    #[serde(flatten)]
+    pub relations: TaskRelations,
+    /// Only included when fetching a single task.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<Vec<TaskAnnotation>>,
+    /// Only included when fetching a single task.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_log: Option<Vec<TaskTimeLog>>,
+}
+
+impl TaskView {
+    pub fn new(task: Task, relations: TaskRelations, coefficients: &UrgencyCoefficients) -> Self {
+        let urgency = urgency(&task, coefficients, Utc::now());
+        TaskView {
+            task,
+            urgency,
+            relations,
+            annotations: None,
+            time_log: None,
+        }
+    }
+
+    pub fn with_annotations(self, annotations: Vec<TaskAnnotation>) -> Self {
+        TaskView {
+            annotations: Some(annotations),
+            ..self
+        }
+    }
+
+    pub fn with_time_log(self, time_log: Vec<TaskTimeLog>) -> Self {
+        TaskView {
+            time_log: Some(time_log),
+            ..self
+        }
+    }
+
+    pub fn is_blocked(&self) -> bool {
+        !self.relations.blocked_by.is_empty()
+    }
+}
+
+/// A timestamped note attached to a task.
+#[derive(
+    Queryable, Identifiable, Associations, Deserialize, Serialize, PartialEq, Eq, Debug, Clone,
+)]
+#[belongs_to(Task)]
+#[table_name = "task_annotation"]
+pub struct TaskAnnotation {
+    pub id: i32,
+    pub task_id: i32,
+    pub time_created: chrono::DateTime<Utc>,
+    pub body: String,
+}
+
+/// An interval of time that was spent on a task, from when it was started to when it was stopped.
+#[derive(
+    Queryable, Identifiable, Associations, Deserialize, Serialize, PartialEq, Eq, Debug, Clone,
+)]
+#[belongs_to(Task)]
+#[table_name = "task_time_log"]
+pub struct TaskTimeLog {
+    pub id: i32,
+    pub task_id: i32,
+    pub time_started: chrono::DateTime<Utc>,
+    /// None while the task is still active.
+    pub time_stopped: Option<chrono::DateTime<Utc>>,
+}
+
+impl TaskTimeLog {
+    /// How long this interval lasted, or has lasted so far if it's still in progress.
+    pub fn duration(&self, now: chrono::DateTime<Utc>) -> chrono::Duration {
+        self.time_stopped.unwrap_or(now) - self.time_started
+    }
+}
+
+/// The total time that was spent on tasks in a project, or on tasks without a project.
+#[derive(QueryableByName, Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct ProjectTime {
+    #[sql_type = "Nullable<Text>"]
+    pub project: Option<String>,
+    #[sql_type = "BigInt"]
+    pub seconds: i64,
+}
+
+/// The body of every error response from the API.
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct ErrorBody {
+    /// What kind of error this is, e.g. `not_found` or `invalid_body`.
+    pub code: String,
+    pub message: String,
+    /// The problems with individual fields of the request body, if there were any.
+    #[serde(default)]
+    pub errors: Vec<FieldError>,
+}
+
+/// A problem with one field of a request body.
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Insertable, Serialize, Debug)]
+#[table_name = "task_annotation"]
+pub struct NewTaskAnnotation {
+    pub task_id: i32,
+    pub body: String,
 }
 
 #[derive(Insertable, Serialize, AsChangeset, Debug)]
@@ -43,27 +352,160 @@ pub struct Task {
 pub struct NewTask {
     pub name: String,
     pub project: Option<String>,
-    pub priority: Option<String>,
+    pub priority: Option<Priority>,
     pub due: Option<chrono::NaiveDate>,
+    #[column_name = "wait_until"]
+    pub wait: Option<chrono::NaiveDate>,
 }
 
+/// A partial update to a task. Fields that are None are left alone, and optional fields that are
+/// Some(None) are cleared.
+#[derive(AsChangeset, Serialize, PartialEq, Eq, Debug, Clone, Default)]
+#[table_name = "task"]
+pub struct TaskChanges {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub project: Option<Option<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<Option<Priority>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub due: Option<Option<chrono::NaiveDate>>,
+    #[column_name = "wait_until"]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wait: Option<Option<chrono::NaiveDate>>,
+}
+
+impl TaskChanges {
+    pub fn is_empty(&self) -> bool {
+        *self == TaskChanges::default()
+    }
+}
+
+/// A new task along with the names of the tags to give it, which `POST /task` creates together.
+#[derive(Serialize, Debug)]
+pub struct NewTaggedTask {
+    #[serde(flatten)]
+    pub task: NewTask,
+    pub tags: Vec<String>,
+}
+
+/// The body of `PATCH /task/<id>`: changes to a task's fields, and the names of tags to add to it
+/// and remove from it, which are all applied together.
+#[derive(Serialize, PartialEq, Eq, Debug, Clone, Default)]
+pub struct TaskPatch {
+    #[serde(flatten)]
+    pub changes: TaskChanges,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub add_tags: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub remove_tags: Vec<String>,
+}
+
+impl TaskPatch {
+    pub fn is_empty(&self) -> bool {
+        *self == TaskPatch::default()
+    }
+}
+
+/// A task that's being imported from another system, so it can already be finished, and keeps
+/// its original creation time.
+#[derive(Insertable, Serialize, Deserialize, Debug, Clone)]
+#[table_name = "task"]
+pub struct NewImportedTask {
+    pub name: String,
+    pub project: Option<String>,
+    pub priority: Option<Priority>,
+    pub mode: Mode,
+    pub time_created: chrono::DateTime<Utc>,
+    pub due: Option<chrono::NaiveDate>,
+    pub recurrence_template_id: Option<i32>,
+    pub wait_until: Option<chrono::NaiveDate>,
+    pub time_completed: Option<chrono::DateTime<Utc>>,
+    pub time_modified: chrono::DateTime<Utc>,
+}
+
+/// A label that can be attached to any number of tasks, e.g. `errand`.
+#[derive(Queryable, Identifiable, Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+#[table_name = "tag"]
+pub struct Tag {
+    pub id: i32,
+    pub name: String,
+}
+
+#[derive(Queryable, Identifiable, Associations, Insertable, PartialEq, Eq, Debug, Clone)]
+#[belongs_to(Task)]
+#[belongs_to(Tag)]
+#[table_name = "task_tag"]
+#[primary_key(task_id, tag_id)]
+pub struct TaskTag {
+    pub task_id: i32,
+    pub tag_id: i32,
+}
+
+/// Records that the task with ID `task_id` can't be started until `depends_on_id` is done.
+#[derive(Queryable, Insertable, PartialEq, Eq, Debug, Clone)]
+#[table_name = "task_dependency"]
+pub struct TaskDependency {
+    pub task_id: i32,
+    pub depends_on_id: i32,
+}
+
+/// Someone who owns tasks and recurrence templates. Users can only see and change their own.
 #[derive(Queryable, Identifiable, Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+#[table_name = "user"]
+pub struct User {
+    pub id: i32,
+    pub time_created: chrono::DateTime<Utc>,
+    pub name: String,
+}
+
+/// A token that lets its user make API requests. Only the token's hash is stored.
+#[derive(
+    Queryable, Identifiable, Associations, Deserialize, Serialize, PartialEq, Eq, Debug, Clone,
+)]
+#[belongs_to(User)]
 #[table_name = "api_token"]
-#[primary_key(token)]
 pub struct ApiToken {
-    pub token: String,
+    pub id: i32,
+    pub time_created: chrono::DateTime<Utc>,
+    pub user_id: i32,
+    #[serde(skip)]
+    pub token_hash: String,
+    /// Which device or person the token was given to, e.g. "laptop".
+    pub label: Option<String>,
+    /// The token stops working at this time, if there is one.
+    pub time_expires: Option<chrono::DateTime<Utc>>,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "api_token"]
+pub struct NewApiToken {
+    pub user_id: i32,
+    pub token_hash: String,
+    pub label: Option<String>,
+    pub time_expires: Option<chrono::DateTime<Utc>>,
 }
 
-#[derive(Queryable, Identifiable, PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+#[derive(
+    Queryable, Identifiable, AsChangeset, PartialEq, Eq, Debug, Clone, Serialize, Deserialize,
+)]
 #[table_name = "recurrence_template"]
+#[changeset_options(treat_none_as_null = "true")]
 pub struct RecurrenceTemplate {
     pub id: i32,
     pub time_created: chrono::DateTime<Utc>,
     pub name: String,
     pub project: Option<String>,
-    pub priority: Option<String>,
+    pub priority: Option<Priority>,
     pub due: chrono::NaiveDate,
     pub days_between_recurrences: i32,
+    /// The user who owns the recurrence template.
+    #[serde(default)]
+    pub user_id: i32,
+    /// Goes up by one whenever the recurrence template is changed, like Task's version.
+    #[serde(default)]
+    pub version: i32,
 }
 
 #[derive(Insertable, AsChangeset, Debug, Serialize, Deserialize)]
@@ -72,7 +514,94 @@ pub struct RecurrenceTemplate {
 pub struct NewRecurrenceTemplate {
     pub name: String,
     pub project: Option<String>,
-    pub priority: Option<String>,
+    pub priority: Option<Priority>,
     pub due: chrono::NaiveDate,
     pub days_between_recurrences: i32,
 }
+
+/// A partial update to a recurrence template, like TaskChanges.
+#[derive(AsChangeset, Serialize, PartialEq, Eq, Debug, Clone, Default)]
+#[table_name = "recurrence_template"]
+pub struct RecurrenceChanges {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub project: Option<Option<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<Option<Priority>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub due: Option<chrono::NaiveDate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub days_between_recurrences: Option<i32>,
+}
+
+impl RecurrenceChanges {
+    pub fn is_empty(&self) -> bool {
+        *self == RecurrenceChanges::default()
+    }
+}
+
+/// A record of a single change to a Task or RecurrenceTemplate, used to implement `rask undo`.
+/// `before` is None if the change created the row.
+#[derive(Queryable, Identifiable, PartialEq, Debug, Clone, Serialize, Deserialize)]
+#[table_name = "task_history"]
+pub struct TaskHistory {
+    pub id: i32,
+    pub time_created: chrono::DateTime<Utc>,
+    #[serde(skip)]
+    pub token_hash: String,
+    pub task_id: Option<i32>,
+    pub recurrence_template_id: Option<i32>,
+    pub before: Option<serde_json::Value>,
+    pub after: serde_json::Value,
+    pub user_id: i32,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "task_history"]
+pub struct NewTaskHistory {
+    pub token_hash: String,
+    pub task_id: Option<i32>,
+    pub recurrence_template_id: Option<i32>,
+    pub before: Option<serde_json::Value>,
+    pub after: serde_json::Value,
+    pub user_id: i32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mode, ModelParseError, Priority};
+
+    #[test]
+    fn test_parse_and_display() {
+        for mode in Mode::ALL.iter() {
+            assert_eq!(mode.to_string().parse(), Ok(*mode));
+        }
+        for priority in Priority::ALL.iter() {
+            assert_eq!(priority.to_string().parse(), Ok(*priority));
+        }
+
+        assert_eq!(
+            "asleep".parse::<Mode>(),
+            Err(ModelParseError::UnknownMode("asleep".to_string()))
+        );
+        assert_eq!(
+            "h".parse::<Priority>(),
+            Err(ModelParseError::UnknownPriority("h".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_serde() {
+        assert_eq!(
+            serde_json::to_string(&Mode::Completed).unwrap(),
+            r#""completed""#
+        );
+        assert_eq!(serde_json::to_string(&Priority::High).unwrap(), r#""H""#);
+        assert_eq!(
+            serde_json::from_str::<Priority>(r#""L""#).unwrap(),
+            Priority::Low
+        );
+        assert!(serde_json::from_str::<Mode>(r#""asleep""#).is_err());
+    }
+}
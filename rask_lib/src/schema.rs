@@ -1,6 +1,11 @@
 table! {
-    api_token (token) {
-        token -> Text,
+    api_token (id) {
+        id -> Int4,
+        time_created -> Timestamptz,
+        user_id -> Int4,
+        token_hash -> Text,
+        label -> Nullable<Text>,
+        time_expires -> Nullable<Timestamptz>,
     }
 }
 
@@ -13,6 +18,15 @@ table! {
         priority -> Nullable<Text>,
         due -> Date,
         days_between_recurrences -> Int4,
+        user_id -> Int4,
+        version -> Int4,
+    }
+}
+
+table! {
+    tag (id) {
+        id -> Int4,
+        name -> Text,
     }
 }
 
@@ -26,13 +40,102 @@ table! {
         time_created -> Timestamptz,
         due -> Nullable<Date>,
         recurrence_template_id -> Nullable<Int4>,
+        wait_until -> Nullable<Date>,
+        display_id -> Nullable<Int4>,
+        time_completed -> Nullable<Timestamptz>,
+        time_modified -> Timestamptz,
+        user_id -> Int4,
+        version -> Int4,
+    }
+}
+
+table! {
+    task_annotation (id) {
+        id -> Int4,
+        task_id -> Int4,
+        time_created -> Timestamptz,
+        body -> Text,
+    }
+}
+
+table! {
+    task_dependency (task_id, depends_on_id) {
+        task_id -> Int4,
+        depends_on_id -> Int4,
+    }
+}
+
+table! {
+    task_history (id) {
+        id -> Int4,
+        time_created -> Timestamptz,
+        token_hash -> Text,
+        task_id -> Nullable<Int4>,
+        recurrence_template_id -> Nullable<Int4>,
+        before -> Nullable<Jsonb>,
+        after -> Jsonb,
+        user_id -> Int4,
+    }
+}
+
+table! {
+    task_tag (task_id, tag_id) {
+        task_id -> Int4,
+        tag_id -> Int4,
+    }
+}
+
+table! {
+    task_time_log (id) {
+        id -> Int4,
+        task_id -> Int4,
+        time_started -> Timestamptz,
+        time_stopped -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    taskwarrior_uuid (user_id, uuid) {
+        user_id -> Int4,
+        uuid -> Text,
+        task_id -> Nullable<Int4>,
+        recurrence_template_id -> Nullable<Int4>,
+    }
+}
+
+table! {
+    user (id) {
+        id -> Int4,
+        time_created -> Timestamptz,
+        name -> Text,
     }
 }
 
+joinable!(api_token -> user (user_id));
+joinable!(recurrence_template -> user (user_id));
 joinable!(task -> recurrence_template (recurrence_template_id));
+joinable!(task -> user (user_id));
+joinable!(task_annotation -> task (task_id));
+joinable!(task_history -> recurrence_template (recurrence_template_id));
+joinable!(task_history -> task (task_id));
+joinable!(task_history -> user (user_id));
+joinable!(task_tag -> tag (tag_id));
+joinable!(task_tag -> task (task_id));
+joinable!(task_time_log -> task (task_id));
+joinable!(taskwarrior_uuid -> recurrence_template (recurrence_template_id));
+joinable!(taskwarrior_uuid -> task (task_id));
+joinable!(taskwarrior_uuid -> user (user_id));
 
 allow_tables_to_appear_in_same_query!(
     api_token,
     recurrence_template,
+    tag,
     task,
+    task_annotation,
+    task_dependency,
+    task_history,
+    task_tag,
+    task_time_log,
+    taskwarrior_uuid,
+    user,
 );
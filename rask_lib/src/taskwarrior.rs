@@ -0,0 +1,587 @@
+//! Conversion between rask's tasks and the JSON format that Taskwarrior's `task export` and
+//! `task import` use.
+
+use crate::models::{
+    Mode, NewImportedTask, NewRecurrenceTemplate, Priority, RecurrenceTemplate, TaskView,
+};
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+use thiserror::Error;
+
+pub const STATUS_PENDING: &str = "pending";
+pub const STATUS_WAITING: &str = "waiting";
+pub const STATUS_COMPLETED: &str = "completed";
+pub const STATUS_DELETED: &str = "deleted";
+pub const STATUS_RECURRING: &str = "recurring";
+
+/// Taskwarrior's timestamps look like `20210901T120000Z`.
+mod tw_date {
+    use chrono::{DateTime, TimeZone, Utc};
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    const FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+    pub fn serialize<S: Serializer>(
+        date: &DateTime<Utc>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&date.format(FORMAT).to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<DateTime<Utc>, D::Error> {
+        let date = String::deserialize(deserializer)?;
+        Utc.datetime_from_str(&date, FORMAT)
+            .map_err(|e| de::Error::custom(format!("invalid date {:?}: {}", date, e)))
+    }
+
+    pub mod optional {
+        use chrono::{DateTime, Utc};
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            date: &Option<DateTime<Utc>>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match date {
+                Some(date) => super::serialize(date, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<DateTime<Utc>>, D::Error> {
+            #[derive(Deserialize)]
+            struct Wrapper(#[serde(with = "super")] DateTime<Utc>);
+
+            Option::<Wrapper>::deserialize(deserializer).map(|date| date.map(|Wrapper(date)| date))
+        }
+    }
+}
+
+/// Older versions of Taskwarrior store `depends` as a comma-separated string of UUIDs,
+/// and newer ones store it as an array.
+fn deserialize_depends<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Depends {
+        List(Vec<String>),
+        CommaSeparated(String),
+    }
+
+    Ok(match Depends::deserialize(deserializer)? {
+        Depends::List(uuids) => uuids,
+        Depends::CommaSeparated(uuids) => uuids
+            .split(',')
+            .filter(|uuid| !uuid.is_empty())
+            .map(str::to_string)
+            .collect(),
+    })
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct TaskwarriorAnnotation {
+    #[serde(with = "tw_date")]
+    pub entry: DateTime<Utc>,
+    pub description: String,
+}
+
+/// A task in Taskwarrior's JSON format. Taskwarrior attributes that rask doesn't have,
+/// like `urgency` or `start`, are ignored when importing.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct TaskwarriorTask {
+    pub uuid: String,
+    pub description: String,
+    pub status: String,
+    #[serde(with = "tw_date")]
+    pub entry: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "tw_date::optional"
+    )]
+    pub due: Option<DateTime<Utc>>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "tw_date::optional"
+    )]
+    pub wait: Option<DateTime<Utc>>,
+    /// When the task was completed or deleted.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "tw_date::optional"
+    )]
+    pub end: Option<DateTime<Utc>>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "tw_date::optional"
+    )]
+    pub modified: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        deserialize_with = "deserialize_depends"
+    )]
+    pub depends: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub annotations: Vec<TaskwarriorAnnotation>,
+    /// How often a `recurring` task recurs, e.g. `weekly` or `3d`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recur: Option<String>,
+    /// For a recurrence of a `recurring` task, the UUID of that task.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum TaskwarriorError {
+    #[error("Task {uuid} has unknown status {status:?}")]
+    UnknownStatus { uuid: String, status: String },
+
+    #[error("Task {uuid} has unknown priority {priority:?}; expected H, M, or L")]
+    UnknownPriority { uuid: String, priority: String },
+
+    #[error("Recurring task {uuid} has unsupported recurrence {recur:?}")]
+    UnsupportedRecurrence { uuid: String, recur: String },
+
+    #[error("Recurring task {uuid} needs a due date and a recurrence")]
+    IncompleteRecurrence { uuid: String },
+}
+
+impl TaskwarriorError {
+    /// The Taskwarrior attribute that the error is about.
+    pub fn field(&self) -> &'static str {
+        match self {
+            TaskwarriorError::UnknownStatus { .. } => "status",
+            TaskwarriorError::UnknownPriority { .. } => "priority",
+            TaskwarriorError::UnsupportedRecurrence { .. }
+            | TaskwarriorError::IncompleteRecurrence { .. } => "recur",
+        }
+    }
+}
+
+/// Dates in rask are local calendar days, and Taskwarrior dates are UTC timestamps of the
+/// local midnight that starts the day. The API's timezone may differ from the user's, so they're
+/// converted at the user's offset from UTC.
+fn to_local_date(date: DateTime<Utc>, utc_offset: FixedOffset) -> NaiveDate {
+    date.with_timezone(&utc_offset).naive_local().date()
+}
+
+fn from_local_date(date: NaiveDate) -> DateTime<Utc> {
+    Local
+        .from_local_datetime(&date.and_hms(0, 0, 0))
+        .earliest()
+        .map(|date| date.with_timezone(&Utc))
+        .unwrap_or_else(|| Utc.from_utc_datetime(&date.and_hms(0, 0, 0)))
+}
+
+/// Parses a Taskwarrior recurrence like `weekly`, `2w`, or `10days` into a number of days.
+/// Months and years are approximated as 30 and 365 days.
+pub fn parse_recurrence(recur: &str) -> Option<i32> {
+    let named = match recur {
+        "daily" | "day" => Some(1),
+        "weekly" | "week" => Some(7),
+        "biweekly" | "fortnight" => Some(14),
+        "monthly" | "month" => Some(30),
+        "bimonthly" => Some(61),
+        "quarterly" => Some(91),
+        "semiannual" => Some(183),
+        "annual" | "yearly" | "year" => Some(365),
+        "biannual" | "biyearly" => Some(730),
+        _ => None,
+    };
+    if named.is_some() {
+        return named;
+    }
+
+    let unit_start = recur
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(recur.len());
+    let (count, unit) = recur.split_at(unit_start);
+    let count = if count.is_empty() {
+        1
+    } else {
+        count.parse::<i32>().ok()?
+    };
+    let days = match unit {
+        "d" | "day" | "days" => 1,
+        "w" | "wk" | "wks" | "week" | "weeks" => 7,
+        "mo" | "mos" | "month" | "months" => 30,
+        "q" | "qtr" | "qtrs" | "quarter" | "quarters" => 91,
+        "y" | "yr" | "yrs" | "year" | "years" => 365,
+        _ => return None,
+    };
+
+    count.checked_mul(days).filter(|&days| days > 0)
+}
+
+/// A stable Taskwarrior UUID for the rask task with ID `task_id`, so that exporting the same
+/// task twice gives it the same UUID.
+pub fn task_uuid(task_id: i32) -> String {
+    format!("00000000-0000-4000-8000-{:012x}", task_id)
+}
+
+/// Like `task_uuid()`, but for recurrence templates.
+pub fn recurrence_uuid(recurrence_id: i32) -> String {
+    format!("00000000-0000-4000-9000-{:012x}", recurrence_id)
+}
+
+/// Converts `task_view` to Taskwarrior's format. Tasks whose wait date is after `today` are
+/// exported as `waiting`. Only unfinished dependencies are included, since those are the only
+/// ones that the API reports.
+pub fn export_task(task_view: &TaskView, today: NaiveDate) -> TaskwarriorTask {
+    let task = &task_view.task;
+    let status = if task.mode == Mode::Completed {
+        STATUS_COMPLETED
+    } else if task.mode == Mode::Deleted {
+        STATUS_DELETED
+    } else if matches!(task.wait_until, Some(wait) if wait > today) {
+        STATUS_WAITING
+    } else {
+        STATUS_PENDING
+    };
+
+    TaskwarriorTask {
+        uuid: task_uuid(task.id),
+        description: task.name.clone(),
+        status: status.to_string(),
+        entry: task.time_created,
+        project: task.project.clone(),
+        priority: task.priority.map(|priority| priority.to_string()),
+        due: task.due.map(from_local_date),
+        wait: task.wait_until.map(from_local_date),
+        end: task.time_completed,
+        modified: Some(task.time_modified),
+        tags: task_view.relations.tags.clone(),
+        depends: task_view
+            .relations
+            .blocked_by
+            .iter()
+            .map(|&task_id| task_uuid(task_id))
+            .collect(),
+        annotations: task_view
+            .annotations
+            .iter()
+            .flatten()
+            .map(|annotation| TaskwarriorAnnotation {
+                entry: annotation.time_created,
+                description: annotation.body.clone(),
+            })
+            .collect(),
+        recur: None,
+        parent: task.recurrence_template_id.map(recurrence_uuid),
+    }
+}
+
+/// Converts `recurrence` to a Taskwarrior `recurring` task.
+pub fn export_recurrence(recurrence: &RecurrenceTemplate) -> TaskwarriorTask {
+    TaskwarriorTask {
+        uuid: recurrence_uuid(recurrence.id),
+        description: recurrence.name.clone(),
+        status: STATUS_RECURRING.to_string(),
+        entry: recurrence.time_created,
+        project: recurrence.project.clone(),
+        priority: recurrence.priority.map(|priority| priority.to_string()),
+        due: Some(from_local_date(recurrence.due)),
+        wait: None,
+        end: None,
+        modified: None,
+        tags: vec![],
+        depends: vec![],
+        annotations: vec![],
+        recur: Some(format!("{}d", recurrence.days_between_recurrences)),
+        parent: None,
+    }
+}
+
+/// A Taskwarrior `recurring` task, ready to be inserted as a RecurrenceTemplate.
+#[derive(Debug)]
+pub struct ImportedRecurrence {
+    pub uuid: String,
+    pub recurrence: NewRecurrenceTemplate,
+}
+
+/// A Taskwarrior task, ready to be inserted. The task's `recurrence_template_id` is filled in
+/// once its parent, if any, has been inserted.
+#[derive(Debug)]
+pub struct ImportedTask {
+    pub uuid: String,
+    pub task: NewImportedTask,
+    pub parent: Option<String>,
+    pub tags: Vec<String>,
+    pub annotations: Vec<String>,
+    pub depends: Vec<String>,
+}
+
+/// Taskwarrior's tasks, converted by `prepare_import()` so that `POST /tasks/import` can insert
+/// them.
+#[derive(Debug, Default)]
+pub struct TaskwarriorImport {
+    pub recurrences: Vec<ImportedRecurrence>,
+    pub tasks: Vec<ImportedTask>,
+}
+
+/// What `POST /tasks/import` returns: the tasks and recurrence templates that it created. Tasks
+/// and recurrence templates whose UUIDs were already imported are skipped, so they aren't included.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ImportedRows {
+    pub tasks: Vec<TaskView>,
+    pub recurrences: Vec<RecurrenceTemplate>,
+}
+
+fn check_priority(
+    uuid: &str,
+    priority: Option<String>,
+) -> Result<Option<Priority>, TaskwarriorError> {
+    priority
+        .map(|priority| {
+            priority
+                .parse()
+                .map_err(|_| TaskwarriorError::UnknownPriority {
+                    uuid: uuid.to_string(),
+                    priority,
+                })
+        })
+        .transpose()
+}
+
+/// Checks `tasks` and converts them to rask's models. Taskwarrior's `pending` and `waiting`
+/// tasks become pending tasks, and `recurring` tasks become recurrence templates. Due and wait
+/// dates are converted at `utc_offset`. If any tasks can't be converted, returns each one's index
+/// and error.
+pub fn prepare_import(
+    tasks: Vec<TaskwarriorTask>,
+    utc_offset: FixedOffset,
+) -> Result<TaskwarriorImport, Vec<(usize, TaskwarriorError)>> {
+    let mut import = TaskwarriorImport::default();
+    let mut errors = vec![];
+
+    for (index, task) in tasks.into_iter().enumerate() {
+        if let Err(error) = prepare_task(&mut import, task, utc_offset) {
+            errors.push((index, error));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(import)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Converts `task` and adds it to `import`.
+fn prepare_task(
+    import: &mut TaskwarriorImport,
+    task: TaskwarriorTask,
+    utc_offset: FixedOffset,
+) -> Result<(), TaskwarriorError> {
+    let priority = check_priority(&task.uuid, task.priority)?;
+
+    if task.status == STATUS_RECURRING {
+        let (due, recur) = match (task.due, task.recur) {
+            (Some(due), Some(recur)) => (due, recur),
+            _ => return Err(TaskwarriorError::IncompleteRecurrence { uuid: task.uuid }),
+        };
+        let days_between_recurrences = match parse_recurrence(&recur) {
+            Some(days) => days,
+            None => {
+                return Err(TaskwarriorError::UnsupportedRecurrence {
+                    uuid: task.uuid,
+                    recur,
+                })
+            }
+        };
+
+        import.recurrences.push(ImportedRecurrence {
+            uuid: task.uuid,
+            recurrence: NewRecurrenceTemplate {
+                name: task.description,
+                project: task.project,
+                priority,
+                due: to_local_date(due, utc_offset),
+                days_between_recurrences,
+            },
+        });
+        return Ok(());
+    }
+
+    let mode = match task.status.as_str() {
+        STATUS_PENDING | STATUS_WAITING => Mode::Pending,
+        STATUS_COMPLETED => Mode::Completed,
+        STATUS_DELETED => Mode::Deleted,
+        _ => {
+            return Err(TaskwarriorError::UnknownStatus {
+                uuid: task.uuid,
+                status: task.status,
+            })
+        }
+    };
+
+    import.tasks.push(ImportedTask {
+        uuid: task.uuid,
+        task: NewImportedTask {
+            name: task.description,
+            project: task.project,
+            priority,
+            mode,
+            time_created: task.entry,
+            due: task.due.map(|due| to_local_date(due, utc_offset)),
+            recurrence_template_id: None,
+            wait_until: task.wait.map(|wait| to_local_date(wait, utc_offset)),
+            time_completed: task.end.filter(|_| mode == Mode::Completed),
+            time_modified: task.modified.unwrap_or(task.entry),
+        },
+        parent: task.parent,
+        tags: task.tags,
+        annotations: task
+            .annotations
+            .into_iter()
+            .map(|annotation| annotation.description)
+            .collect(),
+        depends: task.depends,
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_recurrence, prepare_import, TaskwarriorError, TaskwarriorTask};
+    use crate::models::Mode;
+    use chrono::{FixedOffset, NaiveDate, TimeZone, Utc};
+
+    #[test]
+    fn test_parse_recurrence() {
+        assert_eq!(parse_recurrence("daily"), Some(1));
+        assert_eq!(parse_recurrence("weekly"), Some(7));
+        assert_eq!(parse_recurrence("monthly"), Some(30));
+        assert_eq!(parse_recurrence("3d"), Some(3));
+        assert_eq!(parse_recurrence("2weeks"), Some(14));
+        assert_eq!(parse_recurrence("week"), Some(7));
+        assert_eq!(parse_recurrence("2y"), Some(730));
+        assert_eq!(parse_recurrence("0d"), None);
+        assert_eq!(parse_recurrence("weekdays"), None);
+        assert_eq!(parse_recurrence("3fortnights"), None);
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let task: TaskwarriorTask = serde_json::from_str(
+            r#"{
+                "id": 0,
+                "uuid": "a1b2",
+                "description": "buy milk",
+                "status": "pending",
+                "entry": "20210901T120000Z",
+                "due": "20210905T040000Z",
+                "depends": "c3d4,e5f6",
+                "urgency": 4.2
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(task.entry, Utc.ymd(2021, 9, 1).and_hms(12, 0, 0));
+        assert_eq!(task.due, Some(Utc.ymd(2021, 9, 5).and_hms(4, 0, 0)));
+        assert_eq!(task.wait, None);
+        assert_eq!(task.depends, vec!["c3d4", "e5f6"]);
+
+        let task: TaskwarriorTask = serde_json::from_str(
+            r#"{"uuid": "a1b2", "description": "x", "status": "pending",
+                "entry": "20210901T120000Z", "depends": ["c3d4"]}"#,
+        )
+        .unwrap();
+        assert_eq!(task.depends, vec!["c3d4"]);
+    }
+
+    #[test]
+    fn test_prepare_import_errors() {
+        let task = |status: &str, recur: Option<&str>| TaskwarriorTask {
+            uuid: "a1b2".to_string(),
+            description: "water plants".to_string(),
+            status: status.to_string(),
+            entry: Utc.ymd(2021, 9, 1).and_hms(12, 0, 0),
+            project: None,
+            priority: None,
+            due: Some(Utc.ymd(2021, 9, 5).and_hms(4, 0, 0)),
+            wait: None,
+            end: None,
+            modified: None,
+            tags: vec![],
+            depends: vec![],
+            annotations: vec![],
+            recur: recur.map(str::to_string),
+            parent: None,
+        };
+
+        let utc = FixedOffset::east(0);
+
+        let import = prepare_import(
+            vec![task("recurring", Some("weekly")), task("waiting", None)],
+            utc,
+        )
+        .unwrap();
+        assert_eq!(import.recurrences[0].recurrence.days_between_recurrences, 7);
+        assert_eq!(import.tasks[0].task.mode, Mode::Pending);
+        assert_eq!(
+            import.tasks[0].task.due,
+            Some(NaiveDate::from_ymd(2021, 9, 5))
+        );
+
+        // Dates are converted at the given offset from UTC.
+        let import =
+            prepare_import(vec![task("pending", None)], FixedOffset::west(5 * 3600)).unwrap();
+        assert_eq!(
+            import.tasks[0].task.due,
+            Some(NaiveDate::from_ymd(2021, 9, 4))
+        );
+
+        // Every task that can't be converted is reported, along with its index.
+        assert_eq!(
+            prepare_import(
+                vec![
+                    task("recurring", Some("weekdays")),
+                    task("pending", None),
+                    task("recurring", None),
+                    task("sleeping", None),
+                ],
+                utc,
+            )
+            .unwrap_err(),
+            vec![
+                (
+                    0,
+                    TaskwarriorError::UnsupportedRecurrence {
+                        uuid: "a1b2".to_string(),
+                        recur: "weekdays".to_string()
+                    }
+                ),
+                (
+                    2,
+                    TaskwarriorError::IncompleteRecurrence {
+                        uuid: "a1b2".to_string()
+                    }
+                ),
+                (
+                    3,
+                    TaskwarriorError::UnknownStatus {
+                        uuid: "a1b2".to_string(),
+                        status: "sleeping".to_string()
+                    }
+                ),
+            ]
+        );
+    }
+}
@@ -1,4 +1,5 @@
-use crate::schema::{api_token, task};
+use crate::schema::{api_token, recurrence_template, tag, task, user};
+use crate::token::hash_token;
 use diesel::prelude::*;
 use std::{env, panic};
 
@@ -7,11 +8,28 @@ fn delete_all_tasks(conn: &PgConnection) {
     diesel::delete(task::table).execute(conn).unwrap();
 }
 
+/// Deletes all rows in the `recurrence_template` table.
+fn delete_all_recurrence_templates(conn: &PgConnection) {
+    diesel::delete(recurrence_template::table)
+        .execute(conn)
+        .unwrap();
+}
+
+/// Deletes all rows in the `tag` table.
+fn delete_all_tags(conn: &PgConnection) {
+    diesel::delete(tag::table).execute(conn).unwrap();
+}
+
 /// Deletes all rows in the `api_token` table.
 fn delete_all_tokens(conn: &PgConnection) {
     diesel::delete(api_token::table).execute(conn).unwrap();
 }
 
+/// Deletes all rows in the `user` table.
+fn delete_all_users(conn: &PgConnection) {
+    diesel::delete(user::table).execute(conn).unwrap();
+}
+
 /// Runs a chunk of test code in a setup/teardown block.
 /// Via https://medium.com/@ericdreichert/test-setup-and-teardown-in-rust-without-a-framework-ba32d97aa5ab .
 pub fn run_test<T>(test: T, conn: PgConnection)
@@ -27,15 +45,43 @@ where
     let result = panic::catch_unwind(|| test());
 
     delete_all_tasks(&conn);
+    delete_all_recurrence_templates(&conn);
+    delete_all_tags(&conn);
     delete_all_tokens(&conn);
+    delete_all_users(&conn);
 
     assert!(result.is_ok());
 }
 
-pub fn insert_example_api_token(conn: &PgConnection, example_token: &str) {
+/// Returns the ID of the user named `name`, creating the user if it doesn't exist yet.
+pub fn insert_example_user(conn: &PgConnection, name: &str) -> i32 {
+    diesel::insert_into(user::table)
+        .values(user::name.eq(name))
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .unwrap();
+
+    user::table
+        .filter(user::name.eq(name))
+        .select(user::id)
+        .first(conn)
+        .unwrap()
+}
+
+/// Inserts `example_token` as an API token that belongs to the user named `user_name`.
+pub fn insert_example_user_api_token(conn: &PgConnection, user_name: &str, example_token: &str) {
+    let user_id = insert_example_user(conn, user_name);
+
     diesel::insert_into(api_token::table)
-        .values(api_token::token.eq(example_token))
+        .values((
+            api_token::token_hash.eq(hash_token(example_token)),
+            api_token::user_id.eq(user_id),
+        ))
         .on_conflict_do_nothing()
         .execute(conn)
         .unwrap();
 }
+
+pub fn insert_example_api_token(conn: &PgConnection, example_token: &str) {
+    insert_example_user_api_token(conn, "example", example_token);
+}
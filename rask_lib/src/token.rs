@@ -0,0 +1,20 @@
+use sha2::{Digest, Sha256};
+
+/// Hashes an API token for storage in the `api_token` table. This is the same hash that
+/// Postgres's `encode(sha256(...), 'hex')` produces.
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::hash_token;
+
+    #[test]
+    fn test_hash_token() {
+        assert_eq!(
+            hash_token("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use crate::models::{Mode, Priority, Task};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Weights for each of the terms that make up a task's urgency.
+/// The defaults are borrowed from Taskwarrior.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UrgencyCoefficients {
+    pub priority_high: f64,
+    pub priority_medium: f64,
+    pub priority_low: f64,
+    pub due: f64,
+    pub age: f64,
+    /// Tasks older than this many days get the full `age` coefficient.
+    pub max_age_days: f64,
+    pub project: f64,
+    pub active: f64,
+}
+
+impl Default for UrgencyCoefficients {
+    fn default() -> Self {
+        UrgencyCoefficients {
+            priority_high: 6.0,
+            priority_medium: 3.9,
+            priority_low: 1.8,
+            due: 12.0,
+            age: 2.0,
+            max_age_days: 365.0,
+            project: 1.0,
+            active: 4.0,
+        }
+    }
+}
+
+/// Scales from 0.2 for tasks due two weeks or more from now up to 1.0 for tasks that are a week or more overdue.
+fn due_factor(task: &Task, now: DateTime<Utc>) -> f64 {
+    match task.due {
+        None => 0.0,
+        Some(due) => {
+            let days_overdue = (now.naive_utc().date() - due).num_days() as f64;
+
+            if days_overdue >= 7.0 {
+                1.0
+            } else if days_overdue >= -14.0 {
+                ((days_overdue + 14.0) * 0.8 / 21.0) + 0.2
+            } else {
+                0.2
+            }
+        }
+    }
+}
+
+/// Scales from 0.0 for brand-new tasks up to 1.0 for tasks that are `max_age_days` old.
+fn age_factor(task: &Task, coefficients: &UrgencyCoefficients, now: DateTime<Utc>) -> f64 {
+    if coefficients.max_age_days <= 0.0 {
+        return 1.0;
+    }
+
+    let age_days = (now - task.time_created).num_days() as f64;
+    (age_days / coefficients.max_age_days).clamp(0.0, 1.0)
+}
+
+fn priority_term(task: &Task, coefficients: &UrgencyCoefficients) -> f64 {
+    match task.priority {
+        Some(Priority::High) => coefficients.priority_high,
+        Some(Priority::Medium) => coefficients.priority_medium,
+        Some(Priority::Low) => coefficients.priority_low,
+        None => 0.0,
+    }
+}
+
+/// Returns a score for how urgent `task` is as of `now`; higher scores are more urgent.
+/// Completed and deleted tasks always have an urgency of 0.
+pub fn urgency(task: &Task, coefficients: &UrgencyCoefficients, now: DateTime<Utc>) -> f64 {
+    if !task.mode.is_alive() {
+        return 0.0;
+    }
+
+    let mut score = priority_term(task, coefficients)
+        + coefficients.due * due_factor(task, now)
+        + coefficients.age * age_factor(task, coefficients, now);
+
+    if task.project.is_some() {
+        score += coefficients.project;
+    }
+
+    if task.mode == Mode::Active {
+        score += coefficients.active;
+    }
+
+    score
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{urgency, UrgencyCoefficients};
+    use crate::models::{Mode, Priority, Task};
+    use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+
+    fn now() -> DateTime<Utc> {
+        Utc.ymd(2021, 8, 20).and_hms(12, 0, 0)
+    }
+
+    fn example_task() -> Task {
+        Task {
+            id: 1,
+            name: "clean litterbox".to_string(),
+            project: None,
+            priority: None,
+            mode: Mode::Pending,
+            time_created: now(),
+            due: None,
+            recurrence_template_id: None,
+            wait_until: None,
+            display_id: Some(1),
+            time_completed: None,
+            time_modified: now(),
+            user_id: 1,
+            version: 1,
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn test_new_task_has_no_urgency() {
+        let coefficients = UrgencyCoefficients::default();
+        assert_close(urgency(&example_task(), &coefficients, now()), 0.0);
+    }
+
+    #[test]
+    fn test_priority_and_project() {
+        let coefficients = UrgencyCoefficients::default();
+        let task = Task {
+            priority: Some(Priority::High),
+            project: Some("frank".to_string()),
+            ..example_task()
+        };
+
+        assert_close(urgency(&task, &coefficients, now()), 6.0 + 1.0);
+    }
+
+    #[test]
+    fn test_due_date() {
+        let coefficients = UrgencyCoefficients::default();
+        let due_in = |days: i64| Task {
+            due: Some(NaiveDate::from_ymd(2021, 8, 20) + Duration::days(days)),
+            ..example_task()
+        };
+
+        assert_close(urgency(&due_in(30), &coefficients, now()), 12.0 * 0.2);
+        assert_close(urgency(&due_in(-14), &coefficients, now()), 12.0);
+        assert_close(
+            urgency(&due_in(0), &coefficients, now()),
+            12.0 * ((14.0 * 0.8 / 21.0) + 0.2),
+        );
+    }
+
+    #[test]
+    fn test_age() {
+        let coefficients = UrgencyCoefficients::default();
+        let created_days_ago = |days: i64| Task {
+            time_created: now() - Duration::days(days),
+            ..example_task()
+        };
+
+        assert_close(
+            urgency(&created_days_ago(73), &coefficients, now()),
+            2.0 * 0.2,
+        );
+        assert_close(urgency(&created_days_ago(1000), &coefficients, now()), 2.0);
+    }
+
+    #[test]
+    fn test_mode() {
+        let coefficients = UrgencyCoefficients::default();
+        let task = Task {
+            priority: Some(Priority::High),
+            ..example_task()
+        };
+
+        let active = Task {
+            mode: Mode::Active,
+            ..task.clone()
+        };
+        assert_close(urgency(&active, &coefficients, now()), 6.0 + 4.0);
+
+        let completed = Task {
+            mode: Mode::Completed,
+            ..task
+        };
+        assert_close(urgency(&completed, &coefficients, now()), 0.0);
+    }
+
+    #[test]
+    fn test_custom_coefficients() {
+        let coefficients = UrgencyCoefficients {
+            project: 10.0,
+            ..UrgencyCoefficients::default()
+        };
+        let task = Task {
+            project: Some("frank".to_string()),
+            ..example_task()
+        };
+
+        assert_close(urgency(&task, &coefficients, now()), 10.0);
+    }
+}